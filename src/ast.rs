@@ -1,9 +1,16 @@
 use std::iter::Peekable;
 use std::collections::HashMap;
+use std::fmt;
 
 
+pub mod constants;
+pub mod dead_code;
 pub mod lexer;
+pub mod lints;
+pub mod uses;
+use crate::ast::dead_code::get_definition;
 use crate::ast::lexer::{Token, Keyword, Symbol};
+use crate::Location;
 
 
 fn latex_from_id(id: usize) -> (String, String) {
@@ -27,24 +34,16 @@ fn latex_from_id(id: usize) -> (String, String) {
 }
 
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Variable {
     Absolute(Vec<String>),
     Relative(Vec<String>),
     Std(String),
 } impl Variable {
-    pub fn get_latex(&self, namespaces: &[String], usespace: &HashMap<String, Self>, ids: &HashMap<Vec<String>, usize>) -> String {
-        let consts = vec!["pi", "e", "tau"];
-        let funcs = vec!["floor", "random", "abs", "sin", "cos", "tan", "rgb", "hsv", "length"];
-        if let Self::Absolute(names) = self {
-            if let Some(name) = names.get(0) {
-                if let Some(Self::Std(temp)) = usespace.get(&name.to_string()) {
-                    if temp.is_empty() {
-                        return Self::Std(name.to_string()).get_latex(namespaces, usespace, ids);
-                    }
-                }
-            }
-        }
+    /// The LaTeX for a resolved variable.
+    pub fn get_latex(&self, ids: &HashMap<Vec<String>, usize>) -> String {
+        let consts = ["pi", "e", "tau"];
+        let funcs = ["floor", "random", "abs", "sin", "cos", "tan", "rgb", "hsv", "length"];
         if let Self::Std(name) = self {
             let name = &name.as_str();
             if consts.contains(name) {
@@ -54,8 +53,8 @@ pub enum Variable {
             } else {
                 String::new()
             }
-        } else if let Some(name) = self.get_name(namespaces.to_vec(), usespace) {
-            if let Some(id) = ids.get(&name) {
+        } else if let Some(name) = self.get_resolved_name() {
+            if let Some(id) = ids.get(name) {
                 let (prefix, code) = latex_from_id(*id);
                 if code.is_empty() {
                     prefix
@@ -113,37 +112,59 @@ pub enum Variable {
                 Some(result)
             },
             Self::Absolute(identifiers) => {
-                if let Some(head) = identifiers.get(0) {
-                    if let Some(tail) = usespace.get(head) {
-                        return tail.append(&identifiers.to_vec()).ok()?.get_name(namespaces, usespace);
-                    }
+                // Names bound by `use` stand for the full name they were bound to.
+                if let Some(head) = identifiers.first() {
+                    return match usespace.get(head) {
+                        Some(Self::Absolute(target)) => Some([target, &identifiers[1..]].concat()),
+                        Some(_) => None,
+                        None => Some(identifiers.to_vec()),
+                    };
                 }
-                return Some(identifiers.to_vec());
+                Some(identifiers.to_vec())
             },
             _ => None,
         }
     }
-    fn append(&self, other: &Vec<String>) -> Result<Self, &'static str> {
+    /// What this variable stands for: its full name, or a standard library
+    /// name. None for names that go through a standard library name.
+    pub fn resolve(&self, namespaces: &[String], usespace: &HashMap<String, Self>) -> Option<Self> {
         match self {
-            Self::Relative(identifiers) | Self::Absolute(identifiers) => {
-                {
-                    let temp1 = &mut identifiers.to_vec();
-                    let temp2 = &mut other.to_vec();
-                    temp1.append(temp2);
-                    return Ok(Self::Absolute(temp1.to_vec()));
-                }
+            Self::Std(name) => Some(Self::Std(name.to_string())),
+            Self::Absolute(names) if names.len() == 1 => match usespace.get(&names[0]) {
+                Some(Self::Std(name)) => Some(Self::Std(name.to_string())),
+                _ => self.get_name(namespaces.to_vec(), usespace).map(Self::Absolute),
             },
-            /*
-            Self::Std(name) => if name.is_empty() {
-                if let Some(name) = other.get(0) {
-                    return Ok(Self::Std(name.to_string()));
-                }
-                return Err("Could not append");
-            } else {
-                return Err("Could not append");
-            },
-            */
-            _ => Err("Could not append"),
+            _ => self.get_name(namespaces.to_vec(), usespace).map(Self::Absolute),
+        }
+    }
+    /// The full name of a variable that has been resolved.
+    pub fn get_resolved_name(&self) -> Option<&Vec<String>> {
+        match self {
+            Self::Absolute(names) => Some(names),
+            _ => None,
+        }
+    }
+    /// The names a `use` of this variable binds, each with what it stands for.
+    pub fn get_use_bindings(&self, binding: &UseBinding, namespaces: &[String], usespace: &HashMap<String, Self>) -> Vec<(String, Self)> {
+        let Some(target) = self.resolve(namespaces, usespace) else {
+            return vec![];
+        };
+        match (binding, &target) {
+            (UseBinding::All(keys), Self::Absolute(prefix)) => keys
+                .iter()
+                .map(|key| (key.to_string(), Self::Absolute([prefix.as_slice(), &[key.to_string()]].concat())))
+                .collect(),
+            (UseBinding::All(_), _) => vec![],
+            _ => self.get_use_key(binding).map(|key| (key, target)).into_iter().collect(),
+        }
+    }
+    /// The name bound by a `use` of this variable that does not end in `*`.
+    pub fn get_use_key(&self, binding: &UseBinding) -> Option<String> {
+        match (binding, self) {
+            (UseBinding::Alias(alias), _) => Some(alias.to_string()),
+            (UseBinding::All(_), _) => None,
+            (UseBinding::Name, Self::Absolute(names) | Self::Relative(names)) => names.last().cloned(),
+            (UseBinding::Name, Self::Std(name)) => Some(name.to_string()),
         }
     }
     fn identifiers_from_tokens<'a, I>(tokens: &mut Peekable<I>) -> Result<Vec<String>, &'static str>
@@ -172,6 +193,165 @@ pub enum Variable {
     }
 }
 
+impl fmt::Display for Variable {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Absolute(names) => write!(f, "{}", names.join(".")),
+            Self::Relative(names) => write!(f, "this.{}", names.join(".")),
+            Self::Std(name) => write!(f, "std.{name}"),
+        }
+    }
+}
+
+
+/// What a `use` binds besides its path.
+#[derive(Debug, Clone, PartialEq)]
+pub enum UseBinding {
+    /// `use a.b;` binds `b`.
+    Name,
+    /// `use a.b as c;` binds `c`.
+    Alias(String),
+    /// `use a.*;` binds every member of `a`, listed once the whole tree is known.
+    All(Vec<String>),
+} impl UseBinding {
+    /// The `use` as it is written, without `use` and `;`.
+    pub fn format(&self, variable: &Variable) -> String {
+        match self {
+            Self::Name => variable.to_string(),
+            Self::Alias(alias) => format!("{variable} as {alias}"),
+            Self::All(_) => format!("{variable}.*"),
+        }
+    }
+}
+
+
+/// An attribute from `@[name]` or `@[name = value]`.
+#[derive(Debug)]
+pub struct Attribute {
+    pub name: String,
+    pub value: Option<Token>,
+} impl Attribute {
+    /// Reads the attributes after `@[`, up to and including the closing ']'.
+    pub fn vec_from_tokens<'a, I>(tokens: &mut Peekable<I>) -> Result<Vec<Self>, &'static str>
+    where I: Iterator<Item = &'a Token>
+    {
+        let mut result = vec![];
+        loop {
+            while let Some(Token::Whitespace(_)) = tokens.peek() {
+                tokens.next();
+            }
+            let Some(Token::Identifier(name)) = tokens.next() else {
+                return Err("Expected an attribute name");
+            };
+            while let Some(Token::Whitespace(_)) = tokens.peek() {
+                tokens.next();
+            }
+            let mut value = None;
+            if let Some(Token::Symbol(Symbol::Equal)) = tokens.peek() {
+                tokens.next();
+                while let Some(Token::Whitespace(_)) = tokens.peek() {
+                    tokens.next();
+                }
+                match tokens.next() {
+                    Some(token @ (Token::String(_) | Token::Number(_) | Token::Identifier(_))) => value = Some(Token::from_ref(token)),
+                    _ => Err("Expected a string, a number or a name as the attribute value")?,
+                }
+                while let Some(Token::Whitespace(_)) = tokens.peek() {
+                    tokens.next();
+                }
+            }
+            result.push(Self { name: name.to_string(), value });
+            match tokens.next() {
+                Some(Token::Symbol(Symbol::Comma)) => (),
+                Some(Token::Symbol(Symbol::RightSquare)) => return Ok(result),
+                _ => Err("Expected ',' or ']' after an attribute")?,
+            }
+        }
+    }
+    /// The value of a flag, which is true when it is written without one.
+    pub fn get_bool(&self) -> Result<bool, &'static str> {
+        match &self.value {
+            None => Ok(true),
+            Some(Token::Identifier(value)) if value == "true" => Ok(true),
+            Some(Token::Identifier(value)) if value == "false" => Ok(false),
+            _ => Err("Expected 'true' or 'false' as the attribute value"),
+        }
+    }
+    pub fn get_string(&self) -> Result<String, &'static str> {
+        match &self.value {
+            Some(Token::String(value)) => Ok(value.to_string()),
+            _ => Err("Expected a string as the attribute value"),
+        }
+    }
+}
+
+
+/// How the folder of a namespace is shown, from `@[...]` before it.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct NamespaceAttributes {
+    /// The folder title, the namespace's full name by default.
+    pub title: Option<String>,
+    /// Whether the folder starts collapsed, which it does by default.
+    pub collapsed: Option<bool>,
+    /// Whether everything in the namespace is hidden from the graph.
+    pub hidden: bool,
+    /// Whether the folder is only shown to the author of the graph.
+    pub secret: bool,
+    /// Whether the namespace holds an imported module, which does not see the
+    /// `use`s of the file importing it.
+    pub is_module: bool,
+} impl NamespaceAttributes {
+    pub fn from_attributes(attributes: &[Attribute]) -> Result<Self, &'static str> {
+        let mut result = Self::default();
+        for attribute in attributes {
+            match attribute.name.as_str() {
+                "title" => result.title = Some(attribute.get_string()?),
+                "collapsed" => result.collapsed = Some(attribute.get_bool()?),
+                "hidden" => result.hidden = attribute.get_bool()?,
+                "secret" => result.secret = attribute.get_bool()?,
+                _ => Err("Unknown namespace attribute, one of title, collapsed, hidden or secret")?,
+            }
+        }
+        Ok(result)
+    }
+}
+
+
+/// How an expression or graph is shown, from `@[...]` before it.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ExpressionAttributes {
+    /// Whether it is drawn, which only graphs are by default.
+    pub hidden: Option<bool>,
+    /// Whether it is only shown to the author of the graph.
+    pub secret: bool,
+    /// Whether it can not be edited.
+    pub readonly: bool,
+    /// The Desmos id, for scripts that look the expression up.
+    pub id: Option<String>,
+    /// A description of the expression for screen readers.
+    pub description: Option<String>,
+} impl ExpressionAttributes {
+    pub fn from_attributes(attributes: &[Attribute]) -> Result<Self, &'static str> {
+        let mut result = Self::default();
+        for attribute in attributes {
+            match attribute.name.as_str() {
+                "hidden" => result.hidden = Some(attribute.get_bool()?),
+                "visible" => result.hidden = Some(!attribute.get_bool()?),
+                "secret" => result.secret = attribute.get_bool()?,
+                "readonly" => result.readonly = attribute.get_bool()?,
+                "id" => result.id = Some(match &attribute.value {
+                    Some(Token::Number(id)) => id.to_string(),
+                    _ => attribute.get_string()?,
+                }),
+                "description" => result.description = Some(attribute.get_string()?),
+                _ => Err("Unknown expression attribute, one of hidden, visible, secret, readonly, id or description")?,
+            }
+        }
+        Ok(result)
+    }
+}
+
+
 #[derive(Debug)]
 pub enum ExpressionItem {
     Variable(Variable),
@@ -191,6 +371,11 @@ pub enum ExpressionItem {
                     return Ok(result);
                 },
                 Token::Keyword(Keyword::This | Keyword::Std) | Token::Identifier(_) => result.push(Self::variable_from_tokens(tokens)?),
+                // Numbers end before a second '.', so `1..5` reaches here as `1`, `.`, `5`.
+                Token::Symbol(Symbol::Dot) if matches!(result.last(), Some(Self::Other(Token::Number(_)))) => {
+                    result.push(Self::Other(Token::Symbol(Symbol::Range)));
+                    tokens.next();
+                },
                 Token::Symbol(_) | Token::Number(_) => {
                     result.push(Self::Other(Token::from_ref(token)));
                     tokens.next();
@@ -225,17 +410,24 @@ pub enum ExpressionItem {
             None
         }
     }
-    pub fn get_latex(&self, namespaces: &[String], usespace: &HashMap<String, Variable>, ids: &HashMap<Vec<String>, usize>) -> String {
+    pub fn get_resolved_name(&self) -> Option<&Vec<String>> {
+        if let Self::Variable(v) = self {
+            v.get_resolved_name()
+        } else {
+            None
+        }
+    }
+    pub fn get_latex(&self, ids: &HashMap<Vec<String>, usize>) -> String {
         match self {
-            Self::Variable(v) => v.get_latex(namespaces, usespace, ids),
+            Self::Variable(v) => v.get_latex(ids),
             Self::Other(t) => t.get_latex(),
             // _ => String::new(),
         }
     }
-    pub fn vec_to_latex(vec: Vec<Self>, namespaces: Vec<String>, usespace: &HashMap<String, Variable>, ids: &HashMap<Vec<String>, usize>) -> String {
+    pub fn vec_to_latex(vec: Vec<Self>, ids: &HashMap<Vec<String>, usize>) -> String {
         let mut result = String::new();
         for i in vec {
-            result.push_str(i.get_latex(&namespaces, usespace, ids).as_str());
+            result.push_str(i.get_latex(ids).as_str());
         }
         result
     }
@@ -244,33 +436,219 @@ pub enum ExpressionItem {
 
 #[derive(Debug)]
 pub enum AbstractSyntaxItem {
-    Expression(Vec<ExpressionItem>),
-    Graph(Option<ExpressionItem>, Option<String>, Vec<ExpressionItem>),
-    Use(ExpressionItem),
-    Namespace(String, Vec<Self>),
+    Expression(Vec<ExpressionItem>, ExpressionAttributes),
+    Graph(Option<ExpressionItem>, Option<String>, Vec<ExpressionItem>, ExpressionAttributes),
+    Use(ExpressionItem, UseBinding),
+    Import(Vec<String>),
+    Const(ExpressionItem, Vec<ExpressionItem>),
+    Export(ExpressionItem),
+    /// Marks the name declared by the next item as `pub`.
+    Public(ExpressionItem),
+    Namespace(String, Vec<Self>, NamespaceAttributes),
     Text(String),
+    /// Where the items after it start, as the number of tokens left to parse
+    /// there. `parse` turns these into a `Location`.
+    TokensLeft(usize),
+    /// Where the items after it come from, for diagnostics.
+    Location(Location),
 } impl AbstractSyntaxItem {
-    pub fn get_variable_counts(&self, result: &mut HashMap<Vec<String>, u32>, namespaces: &[String], usespace: &HashMap<String, Variable>) {
+    /// Counts how often each resolved name is used.
+    pub fn get_variable_counts(&self, result: &mut HashMap<Vec<String>, u32>) {
         match self {
-            Self::Expression(items) | Self::Graph(_, _, items) => for i in items {
-                if let Some(name) = i.get_variable_name(namespaces.to_vec(), usespace) {
+            Self::Expression(items, _) | Self::Graph(_, _, items, _) => for i in items {
+                if let Some(name) = i.get_resolved_name() {
                     result
-                        .entry(name)
+                        .entry(name.to_vec())
                         .and_modify(|count| *count += 1)
                         .or_insert(1);
                 }
             },
-            Self::Namespace(name, items) => {
-                let names = &mut namespaces.to_vec();
-                names.push(name.to_string());
+            Self::Namespace(_, items, _) => {
                 for i in items {
-                    i.get_variable_counts(result, names, usespace);
+                    i.get_variable_counts(result);
                 }
             },
             _ => (),
         }
     }
-    pub fn use_from_tokens<'a, I>(tokens: &mut Peekable<I>) -> Result<Self, &'static str>
+    /// Reads a `use` up to its ';', as one item per name it binds for grouped
+    /// uses like `use a.{b, c.d as e, f.*};`.
+    pub fn vec_use_from_tokens<'a, I>(tokens: &mut Peekable<I>) -> Result<Vec<Self>, &'static str>
+    where I: Iterator<Item = &'a Token>
+    {
+        let mut result = vec![];
+        Self::fill_use_from_tokens(&mut result, tokens, vec![])?;
+        if let Some(Token::Whitespace(_)) = tokens.peek() {
+            tokens.next();
+        }
+        if let Some(Token::Symbol(Symbol::Semicolon)) = tokens.next() {} else {
+            Err("';' expected")?;
+        }
+        Ok(result)
+    }
+    fn fill_use_from_tokens<'a, I>(result: &mut Vec<Self>, tokens: &mut Peekable<I>, mut path: Vec<String>) -> Result<(), &'static str>
+    where I: Iterator<Item = &'a Token>
+    {
+        loop {
+            while let Some(Token::Whitespace(_)) = tokens.peek() {
+                tokens.next();
+            }
+            match tokens.next() {
+                Some(Token::Identifier(name)) => path.push(name.to_string()),
+                Some(Token::Keyword(Keyword::This)) if path.is_empty() => path.push("this".to_string()),
+                Some(Token::Keyword(Keyword::Std)) if path.is_empty() => path.push("std".to_string()),
+                Some(Token::Symbol(Symbol::Mul)) if !path.is_empty() => {
+                    result.push(Self::Use(Self::use_variable_from_path(path)?, UseBinding::All(vec![])));
+                    return Ok(());
+                },
+                Some(Token::Symbol(Symbol::LeftCurly)) if !path.is_empty() => {
+                    loop {
+                        Self::fill_use_from_tokens(result, tokens, path.to_vec())?;
+                        while let Some(Token::Whitespace(_)) = tokens.peek() {
+                            tokens.next();
+                        }
+                        match tokens.next() {
+                            Some(Token::Symbol(Symbol::Comma)) => (),
+                            Some(Token::Symbol(Symbol::RightCurly)) => return Ok(()),
+                            _ => Err("Expected ',' or '}' in a grouped 'use'")?,
+                        }
+                    }
+                },
+                _ => Err("Expected a name in 'use'")?,
+            }
+            while let Some(Token::Whitespace(_)) = tokens.peek() {
+                tokens.next();
+            }
+            match tokens.peek() {
+                Some(Token::Symbol(Symbol::Dot)) => {
+                    tokens.next();
+                },
+                Some(Token::Identifier(word)) if word == "as" => {
+                    tokens.next();
+                    while let Some(Token::Whitespace(_)) = tokens.peek() {
+                        tokens.next();
+                    }
+                    let Some(Token::Identifier(alias)) = tokens.next() else {
+                        return Err("Expected a name after 'as'");
+                    };
+                    result.push(Self::Use(Self::use_variable_from_path(path)?, UseBinding::Alias(alias.to_string())));
+                    return Ok(());
+                },
+                _ => {
+                    result.push(Self::Use(Self::use_variable_from_path(path)?, UseBinding::Name));
+                    return Ok(());
+                },
+            }
+        }
+    }
+    fn use_variable_from_path(mut path: Vec<String>) -> Result<ExpressionItem, &'static str> {
+        let variable = match path.first().map(String::as_str) {
+            Some("this") if path.len() > 1 => Variable::Relative(path.split_off(1)),
+            Some("std") if path.len() == 2 => Variable::Std(path.remove(1)),
+            Some("this" | "std") => Err("Expected a name after 'this.' or 'std.'")?,
+            _ => Variable::Absolute(path),
+        };
+        Ok(ExpressionItem::Variable(variable))
+    }
+    pub fn import_from_tokens<'a, I>(tokens: &mut Peekable<I>) -> Result<Self, &'static str>
+    where I: Iterator<Item = &'a Token>
+    {
+        match Self::vec_use_from_tokens(tokens)?.as_slice() {
+            [Self::Use(ExpressionItem::Variable(Variable::Absolute(names)), UseBinding::Name)] => Ok(Self::Import(names.to_vec())),
+            _ => Err("Expected a module path after 'import'"),
+        }
+    }
+    pub fn export_from_tokens<'a, I>(tokens: &mut Peekable<I>) -> Result<Self, &'static str>
+    where I: Iterator<Item = &'a Token>
+    {
+        match Self::vec_use_from_tokens(tokens)?.pop() {
+            Some(Self::Use(variable, UseBinding::Name)) => Ok(Self::Export(variable)),
+            _ => Err("Expected a name after 'export'"),
+        }
+    }
+    /// Reads what follows `pub`: a definition, a `const`, a `namespace` or a
+    /// `for` loop, each preceded by a marker for the names it declares.
+    pub fn pub_from_tokens<'a, I>(tokens: &mut Peekable<I>) -> Result<Vec<Self>, &'static str>
+    where I: ExactSizeIterator<Item = &'a Token>
+    {
+        if let Some(Token::Whitespace(_)) = tokens.peek() {
+            tokens.next();
+        }
+        let items = match tokens.peek() {
+            Some(Token::Keyword(Keyword::Namespace)) => {
+                tokens.next();
+                vec![Self::namespace_from_tokens(tokens)?]
+            },
+            Some(Token::Keyword(Keyword::Const)) => {
+                tokens.next();
+                vec![Self::const_from_tokens(tokens)?]
+            },
+            Some(Token::Keyword(Keyword::For)) => {
+                tokens.next();
+                Self::for_from_tokens(tokens)?
+            },
+            Some(Token::Keyword(Keyword::This | Keyword::Std) | Token::Identifier(_)) => vec![Self::expression_from_tokens(tokens)?],
+            _ => Err("Expected a definition, 'const', 'namespace' or 'for' after 'pub'")?,
+        };
+        let mut result = vec![];
+        for item in items {
+            let variable = match &item {
+                Self::Namespace(name, ..) => Variable::Relative(vec![name.to_string()]),
+                Self::Const(ExpressionItem::Variable(variable), _) => variable.clone(),
+                Self::Expression(items, _) => get_definition(items)
+                    .ok_or("Expected a definition, 'const', 'namespace' or 'for' after 'pub'")?
+                    .clone(),
+                _ => Err("Expected a definition, 'const', 'namespace' or 'for' after 'pub'")?,
+            };
+            result.push(Self::Public(ExpressionItem::Variable(variable)));
+            result.push(item);
+        }
+        Ok(result)
+    }
+    /// Reads the attributes after `@[` and the namespace, graph or expression
+    /// they apply to, which can be `pub` or come from a `for` loop.
+    pub fn attributed_from_tokens<'a, I>(tokens: &mut Peekable<I>) -> Result<Vec<Self>, &'static str>
+    where I: ExactSizeIterator<Item = &'a Token>
+    {
+        let attributes = Attribute::vec_from_tokens(tokens)?;
+        while let Some(Token::Whitespace(_)) = tokens.peek() {
+            tokens.next();
+        }
+        let mut result = match tokens.peek() {
+            Some(Token::Keyword(Keyword::Namespace)) => {
+                tokens.next();
+                vec![Self::namespace_from_tokens(tokens)?]
+            },
+            Some(Token::Keyword(Keyword::For)) => {
+                tokens.next();
+                Self::for_from_tokens(tokens)?
+            },
+            Some(Token::Keyword(Keyword::Pub)) => {
+                tokens.next();
+                Self::pub_from_tokens(tokens)?
+            },
+            Some(Token::Keyword(Keyword::Graph)) => {
+                tokens.next();
+                vec![Self::graph_from_tokens(tokens)?]
+            },
+            Some(Token::Keyword(Keyword::This | Keyword::Std) | Token::Identifier(_) | Token::Symbol(_) | Token::Number(_)) => {
+                vec![Self::expression_from_tokens(tokens)?]
+            },
+            _ => Err("Expected a namespace, a graph or an expression after attributes")?,
+        };
+        for item in &mut result {
+            match item {
+                Self::Namespace(_, _, item_attributes) => *item_attributes = NamespaceAttributes::from_attributes(&attributes)?,
+                Self::Expression(_, item_attributes) | Self::Graph(_, _, _, item_attributes) => {
+                    *item_attributes = ExpressionAttributes::from_attributes(&attributes)?;
+                },
+                Self::Public(_) => (),
+                _ => Err("Expected a namespace, a graph or an expression after attributes")?,
+            }
+        }
+        Ok(result)
+    }
+    pub fn const_from_tokens<'a, I>(tokens: &mut Peekable<I>) -> Result<Self, &'static str>
     where I: Iterator<Item = &'a Token>
     {
         if let Some(Token::Whitespace(_)) = tokens.peek() {
@@ -280,10 +658,10 @@ pub enum AbstractSyntaxItem {
         if let Some(Token::Whitespace(_)) = tokens.peek() {
             tokens.next();
         }
-        if let Some(Token::Symbol(Symbol::Semicolon)) = tokens.next() {} else {
-            Err("';' expected")?;
+        if let Some(Token::Symbol(Symbol::Equal)) = tokens.next() {} else {
+            Err("'=' expected after the constant name")?;
         }
-        Ok(Self::Use(variable))
+        Ok(Self::Const(variable, ExpressionItem::vec_from_tokens(tokens)?))
     }
     pub fn graph_from_tokens<'a, I>(tokens: &mut Peekable<I>) -> Result<Self, &'static str>
     where I: Iterator<Item = &'a Token>
@@ -314,10 +692,10 @@ pub enum AbstractSyntaxItem {
             Err("':' expected")?;
         }
         let graph = ExpressionItem::vec_from_tokens(tokens)?;
-        Ok(Self::Graph(color, opacity.cloned(), graph))
+        Ok(Self::Graph(color, opacity.cloned(), graph, ExpressionAttributes::default()))
     }
     pub fn namespace_from_tokens<'a, I>(tokens: &mut Peekable<I>) -> Result<Self, &'static str>
-    where I: Iterator<Item = &'a Token>
+    where I: ExactSizeIterator<Item = &'a Token>
     {
         if let Some(Token::Whitespace(false)) = tokens.peek() {
             tokens.next();
@@ -327,7 +705,7 @@ pub enum AbstractSyntaxItem {
                 tokens.next();
             }
             return if let Some(&Token::Symbol(Symbol::LeftCurly)) = tokens.next() {
-                 Ok(Self::Namespace(name.to_string(), AbstractSyntaxTree::from_tokens(tokens, true)?))
+                 Ok(Self::Namespace(name.to_string(), AbstractSyntaxTree::from_tokens(tokens, true)?, NamespaceAttributes::default()))
             } else {
                  Err("Unexpected token")
             };
@@ -337,7 +715,115 @@ pub enum AbstractSyntaxItem {
     pub fn expression_from_tokens<'a, I>(tokens: &mut Peekable<I>) -> Result<Self, &'static str>
     where I: Iterator<Item = &'a Token>
     {
-        Ok(Self::Expression(ExpressionItem::vec_from_tokens(tokens)?))
+        Ok(Self::Expression(ExpressionItem::vec_from_tokens(tokens)?, ExpressionAttributes::default()))
+    }
+    /// Unrolls `for i in 0..10 { ... }` into one namespace per iteration,
+    /// named `i0` to `i9`, with every `i` in the body replaced by its value.
+    pub fn for_from_tokens<'a, I>(tokens: &mut Peekable<I>) -> Result<Vec<Self>, &'static str>
+    where I: ExactSizeIterator<Item = &'a Token>
+    {
+        let left = tokens.len();
+        let mut next = || {
+            while let Some(Token::Whitespace(_)) = tokens.peek() {
+                tokens.next();
+            }
+            tokens.next()
+        };
+        let name = match next() {
+            Some(Token::Identifier(name)) => name.to_string(),
+            _ => Err("Expected a loop variable after 'for'")?,
+        };
+        // 'in' is only special here, so it stays a valid name everywhere else.
+        if !matches!(next(), Some(Token::Identifier(i)) if i == "in") {
+            Err("Expected 'in' after the loop variable")?;
+        }
+        let start = Self::read_bound(&mut next)?;
+        // A number right before '..' takes one of its dots.
+        if !matches!(next(), Some(Token::Symbol(Symbol::Dot | Symbol::Range))) {
+            Err("Expected a range like '0..10' and a '{' after 'in'")?;
+        }
+        let end = Self::read_bound(&mut next)?;
+        if !matches!(next(), Some(Token::Symbol(Symbol::LeftCurly))) {
+            Err("Expected a range like '0..10' and a '{' after 'in'")?;
+        }
+        let mut body = vec![];
+        let mut depth = 0;
+        loop {
+            let token = tokens.next().ok_or("Unclosed loop")?;
+            match token {
+                Token::Symbol(Symbol::LeftCurly) => depth += 1,
+                Token::Symbol(Symbol::RightCurly) if depth == 0 => break,
+                Token::Symbol(Symbol::RightCurly) => depth -= 1,
+                _ => (),
+            }
+            body.push(token);
+        }
+        let mut result = vec![];
+        for value in start..end {
+            let tokens = Self::substitute_tokens(&body, &name, value);
+            let namespace = format!("{name}{}", if value < 0 { format!("m{}", -value) } else { value.to_string() });
+            let mut items = AbstractSyntaxTree::from_tokens(&mut tokens.iter().peekable(), false)?;
+            // The body was parsed from its own tokens, so its items point at the loop.
+            Self::set_tokens_left(&mut items, left);
+            result.push(Self::Namespace(namespace, items, NamespaceAttributes::default()));
+        }
+        Ok(result)
+    }
+    fn set_tokens_left(ast: &mut AbstractSyntaxTree, left: usize) {
+        for item in ast {
+            match item {
+                Self::TokensLeft(l) => *l = left,
+                Self::Namespace(_, items, _) => Self::set_tokens_left(items, left),
+                _ => (),
+            }
+        }
+    }
+    /// Reads one loop bound, a whole number that can be negated or in
+    /// parentheses, like an enclosing loop variable substituted as `(-1)`.
+    fn read_bound<'a>(next: &mut impl FnMut() -> Option<&'a Token>) -> Result<i64, &'static str> {
+        match next() {
+            Some(Token::Number(n)) => n.parse().map_err(|_| "Loop bounds must be whole numbers"),
+            Some(Token::Symbol(Symbol::Sub)) => Self::read_bound(next).map(|bound| -bound),
+            Some(Token::Symbol(Symbol::LeftParen)) => {
+                let bound = Self::read_bound(next)?;
+                match next() {
+                    Some(Token::Symbol(Symbol::RightParen)) => Ok(bound),
+                    _ => Err("Expected a ')' after the loop bound"),
+                }
+            },
+            _ => Err("Expected a range like '0..10' and a '{' after 'in'"),
+        }
+    }
+    /// Replaces the loop variable `name` with `value`, but not members like `this.name`.
+    fn substitute_tokens(body: &[&Token], name: &str, value: i64) -> Vec<Token> {
+        let mut result = vec![];
+        let mut is_member = false;
+        let mut is_name = false;
+        for &token in body {
+            match token {
+                Token::Identifier(i) if i == name && !is_member => {
+                    if value < 0 {
+                        result.push(Token::Symbol(Symbol::LeftParen));
+                        result.push(Token::Symbol(Symbol::Sub));
+                        result.push(Token::Number((-value).to_string()));
+                        result.push(Token::Symbol(Symbol::RightParen));
+                    } else {
+                        result.push(Token::Number(value.to_string()));
+                    }
+                },
+                _ => result.push(Token::from_ref(token)),
+            }
+            // A '.' after a number is half of a range like `0..i`.
+            match token {
+                Token::Whitespace(_) => (),
+                Token::Symbol(Symbol::Dot) => is_member = is_name,
+                _ => is_member = false,
+            }
+            if !matches!(token, Token::Whitespace(_)) {
+                is_name = matches!(token, Token::Identifier(_) | Token::Keyword(Keyword::This | Keyword::Std));
+            }
+        }
+        result
     }
 }
 
@@ -347,9 +833,9 @@ pub type AbstractSyntaxTree = Vec<AbstractSyntaxItem>;
 
 pub trait AbstractSyntaxTreeTrait {
     fn from_tokens<'a, I>(tokens: &mut Peekable<I>, is_namespace: bool) -> Result<Self, &'static str>
-    where I: Iterator<Item = &'a Token>, Self: Sized;
+    where I: ExactSizeIterator<Item = &'a Token>, Self: Sized;
     fn fill_from_tokens<'a, I>(&mut self, tokens: &mut Peekable<I>, is_namespace: bool) -> Result<(), &'static str>
-    where I: Iterator<Item = &'a Token>, Self: Sized;
+    where I: ExactSizeIterator<Item = &'a Token>, Self: Sized;
     fn get_variable_ids(&self) -> HashMap<Vec<String>, usize>;
 }
 
@@ -357,12 +843,12 @@ pub trait AbstractSyntaxTreeTrait {
 impl AbstractSyntaxTreeTrait for AbstractSyntaxTree {
     fn get_variable_ids(&self) -> HashMap<Vec<String>, usize> {
         let mut counts = HashMap::new();
-        let usespace = &HashMap::new();
         for i in self {
-            i.get_variable_counts(&mut counts, &[], usespace);
+            i.get_variable_counts(&mut counts);
         }
         let mut hash_vec: Vec<(&Vec<String>, &u32)> = counts.iter().collect();
-        hash_vec.sort_by(|a, b| b.1.cmp(a.1));
+        // Ties go by name so the same code always gets the same LaTeX.
+        hash_vec.sort_by(|a, b| b.1.cmp(a.1).then_with(|| a.0.cmp(b.0)));
         let mut result = HashMap::new();
         for (index, (item, _)) in hash_vec.iter().enumerate() {
             result.insert(item.to_vec(), index);
@@ -370,17 +856,20 @@ impl AbstractSyntaxTreeTrait for AbstractSyntaxTree {
         result
     }
     fn from_tokens<'a, I>(tokens: &mut Peekable<I>, is_namespace: bool) -> Result<Self, &'static str>
-    where I: Iterator<Item = &'a Token>
+    where I: ExactSizeIterator<Item = &'a Token>
     {
         let mut result = vec![];
         result.fill_from_tokens(tokens, is_namespace)?;
         Ok(result)
     }
     fn fill_from_tokens<'a, I>(&mut self, tokens: &mut Peekable<I>, is_namespace: bool) -> Result<(), &'static str>
-    where I: Iterator<Item = &'a Token>
+    where I: ExactSizeIterator<Item = &'a Token>
     {
         type A = AbstractSyntaxItem;
-        while let Some(token) = tokens.peek() {
+        while let Some(&token) = tokens.peek() {
+            if !matches!(token, Token::Whitespace(_) | Token::Symbol(Symbol::RightCurly)) {
+                self.push(A::TokensLeft(tokens.len()));
+            }
             match token {
                 Token::Keyword(Keyword::Namespace) => {
                     tokens.next();
@@ -399,7 +888,31 @@ impl AbstractSyntaxTreeTrait for AbstractSyntaxTree {
                 },
                 Token::Keyword(Keyword::Use) => {
                     tokens.next();    
-                    self.push(A::use_from_tokens(tokens)?);
+                    self.append(&mut A::vec_use_from_tokens(tokens)?);
+                },
+                Token::Keyword(Keyword::Import) => {
+                    tokens.next();
+                    self.push(A::import_from_tokens(tokens)?);
+                },
+                Token::Keyword(Keyword::Export) => {
+                    tokens.next();
+                    self.push(A::export_from_tokens(tokens)?);
+                },
+                Token::Keyword(Keyword::Const) => {
+                    tokens.next();
+                    self.push(A::const_from_tokens(tokens)?);
+                },
+                Token::Keyword(Keyword::Pub) => {
+                    tokens.next();
+                    self.append(&mut A::pub_from_tokens(tokens)?);
+                },
+                Token::Symbol(Symbol::Attributes) => {
+                    tokens.next();
+                    self.append(&mut A::attributed_from_tokens(tokens)?);
+                },
+                Token::Keyword(Keyword::For) => {
+                    tokens.next();
+                    self.append(&mut A::for_from_tokens(tokens)?);
                 },
                 Token::Text(t) => {
                     tokens.next();
@@ -416,3 +929,69 @@ impl AbstractSyntaxTreeTrait for AbstractSyntaxTree {
         }
     }
 }
+
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use crate::ast::lexer::Token;
+    use crate::ast::{AbstractSyntaxItem, AbstractSyntaxTree, AbstractSyntaxTreeTrait, ExpressionItem};
+
+    fn parse(source: &str) -> Result<AbstractSyntaxTree, &'static str> {
+        let tokens = Token::vec_from_chars(&mut source.chars().peekable())?;
+        AbstractSyntaxTree::from_tokens(&mut tokens.iter().peekable(), false)
+    }
+
+    /// Lists every expression as `namespace path: latex`, leaving out variables.
+    fn outline(ast: AbstractSyntaxTree, namespaces: &[String], result: &mut Vec<String>) {
+        for item in ast {
+            match item {
+                AbstractSyntaxItem::Namespace(name, items, _) => outline(items, &[namespaces, &[name]].concat(), result),
+                AbstractSyntaxItem::Expression(items, _) => {
+                    let latex = ExpressionItem::vec_to_latex(items, &HashMap::new());
+                    result.push(format!("{}: {latex}", namespaces.join(".")));
+                },
+                _ => (),
+            }
+        }
+    }
+
+    fn unroll(source: &str) -> Vec<String> {
+        let mut result = vec![];
+        outline(parse(source).unwrap(), &[], &mut result);
+        result
+    }
+
+    #[test]
+    fn unrolls_into_a_namespace_per_iteration() {
+        assert_eq!(unroll("for i in 0..3 { this.p = i; }"), ["i0: =0", "i1: =1", "i2: =2"]);
+        assert_eq!(unroll("for i in 2..2 { this.p = i; }"), Vec::<String>::new());
+    }
+
+    #[test]
+    fn unrolls_negative_bounds() {
+        assert_eq!(unroll("for i in -2..0 { this.p = i; }"), ["im2: =\\left(-2\\right)", "im1: =\\left(-1\\right)"]);
+        assert_eq!(unroll("for i in -2..-4 { this.p = i; }"), Vec::<String>::new());
+        assert_eq!(
+            unroll("for i in 0..2 { for j in -i..i { this.p = (i, j); } }"),
+            ["i1.jm1: =\\left(1,\\left(-1\\right)\\right)", "i1.j0: =\\left(1,0\\right)"],
+        );
+    }
+
+    #[test]
+    fn keeps_members_named_like_the_variable() {
+        assert_eq!(unroll("for i in 0..2 { this.i = i + g.i; }"), ["i0: =0+", "i1: =1+"]);
+    }
+
+    #[test]
+    fn rejects_bad_loops() {
+        assert_eq!(parse("for i in 0..1.5 { this.p = i; }").unwrap_err(), "Loop bounds must be whole numbers");
+        assert_eq!(parse("for i in 0 { }").unwrap_err(), "Expected a range like '0..10' and a '{' after 'in'");
+        assert_eq!(parse("for i in 0..1 2 { }").unwrap_err(), "Expected a range like '0..10' and a '{' after 'in'");
+        assert_eq!(parse("for i in 0..1) { }").unwrap_err(), "Expected a range like '0..10' and a '{' after 'in'");
+        assert_eq!(parse("for i in (0..1 { }").unwrap_err(), "Expected a ')' after the loop bound");
+        assert_eq!(parse("for i 0..1 { }").unwrap_err(), "Expected 'in' after the loop variable");
+        assert_eq!(parse("for i in 0..1 { this.p = i;").unwrap_err(), "Unclosed loop");
+    }
+}
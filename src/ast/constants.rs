@@ -0,0 +1,474 @@
+use std::collections::HashMap;
+
+use crate::ast::lexer::{Symbol, Token};
+use crate::ast::{AbstractSyntaxItem, AbstractSyntaxTree, ExpressionItem, Variable};
+use crate::Diagnostic;
+
+
+type Name = Vec<String>;
+type Constants = HashMap<Name, f64>;
+
+
+/// Symbols that bind looser than any arithmetic, so the numbers between them
+/// can be folded on their own.
+const SEPARATORS: [Symbol; 15] = [
+    Symbol::Equal,
+    Symbol::LT,
+    Symbol::GT,
+    Symbol::LE,
+    Symbol::GE,
+    Symbol::AddEq,
+    Symbol::SubEq,
+    Symbol::MulEq,
+    Symbol::DivEq,
+    Symbol::Arrow,
+    Symbol::Comma,
+    Symbol::Colon,
+    Symbol::Elipsis,
+    Symbol::Range,
+    Symbol::Semicolon,
+];
+
+
+/// Evaluates `const` declarations, inlines them where they are used and folds
+/// the parts of every expression that only involve numbers, like `2 * std.pi / 3`.
+/// Expressions can use constants declared anywhere, while a constant can only
+/// use the ones declared before it. Trigonometry is left to Desmos, since its
+/// result depends on the calculator's angle mode. Names must have been resolved.
+pub fn fold_constants(ast: &mut AbstractSyntaxTree) -> Result<(), Diagnostic> {
+    let mut declarations = vec![];
+    take_declarations(ast, &mut declarations)?;
+    let mut constants = Constants::new();
+    let mut values = vec![];
+    for index in 0..declarations.len() {
+        check_order(&declarations, index)?;
+        let name = declarations[index].0.to_vec();
+        let folded = fold_items(std::mem::take(&mut declarations[index].1), &constants);
+        let value = Evaluator::new(&folded).evaluate()
+            .ok_or(format!("Constant '{}' does not evaluate to a number", name.join(".")))?;
+        constants.insert(name, value);
+        values.push(folded);
+    }
+    fold_tree(ast, &constants, &mut values.into_iter());
+    Ok(())
+}
+
+
+/// Takes the name and value of every `const` in the order they are declared,
+/// for `fold_tree` to put the folded values back.
+fn take_declarations(ast: &mut AbstractSyntaxTree, result: &mut Vec<(Name, Vec<ExpressionItem>)>) -> Result<(), Diagnostic> {
+    type T = AbstractSyntaxItem;
+    for item in ast.iter_mut() {
+        match item {
+            T::Const(variable, items) => {
+                let name = variable.get_resolved_name().ok_or("Constants need a name")?;
+                result.push((name.to_vec(), std::mem::take(items)));
+            },
+            T::Namespace(_, items, _) => take_declarations(items, result)?,
+            _ => (),
+        }
+    }
+    Ok(())
+}
+
+
+/// Checks that the constant at `index` only uses constants declared before it,
+/// which also rules out constants that depend on each other.
+fn check_order(declarations: &[(Name, Vec<ExpressionItem>)], index: usize) -> Result<(), String> {
+    let (name, items) = &declarations[index];
+    let Some(later) = get_used_constants(items, declarations).find(|&i| i >= index) else {
+        return Ok(());
+    };
+    let mut path = vec![index];
+    if find_path(declarations, later, index, &mut path) {
+        let names: Vec<String> = path.iter().map(|&i| declarations[i].0.join(".")).collect();
+        return Err(format!("Constant cycle: {}", names.join(" -> ")));
+    }
+    Err(format!("Constant '{}' uses '{}', which is declared after it", name.join("."), declarations[later].0.join(".")))
+}
+
+
+/// Whether the constant at `from` leads back to the one at `to`, adding the
+/// constants on the way to `path`.
+fn find_path(declarations: &[(Name, Vec<ExpressionItem>)], from: usize, to: usize, path: &mut Vec<usize>) -> bool {
+    if path[1..].contains(&from) {
+        return false;
+    }
+    path.push(from);
+    if from == to || get_used_constants(&declarations[from].1, declarations).any(|i| find_path(declarations, i, to, path)) {
+        return true;
+    }
+    path.pop();
+    false
+}
+
+
+/// The indices of the constants `items` uses.
+fn get_used_constants<'a>(items: &'a [ExpressionItem], declarations: &'a [(Name, Vec<ExpressionItem>)]) -> impl Iterator<Item = usize> + 'a {
+    items.iter()
+        .filter_map(|item| item.get_resolved_name())
+        .filter_map(|name| declarations.iter().position(|(n, _)| n == name))
+}
+
+
+fn fold_tree(ast: &mut AbstractSyntaxTree, constants: &Constants, values: &mut impl Iterator<Item = Vec<ExpressionItem>>) {
+    type T = AbstractSyntaxItem;
+    for item in ast.iter_mut() {
+        match item {
+            T::Const(_, items) => *items = values.next().unwrap_or_default(),
+            T::Expression(items, _) | T::Graph(_, _, items, _) => {
+                *items = fold_items(std::mem::take(items), constants);
+            },
+            T::Namespace(_, items, _) => fold_tree(items, constants, values),
+            _ => (),
+        }
+    }
+}
+
+
+fn fold_items(items: Vec<ExpressionItem>, constants: &Constants) -> Vec<ExpressionItem> {
+    let mut result = vec![];
+    for item in items {
+        if let ExpressionItem::Variable(variable) = &item {
+            if let Some(&value) = variable.get_resolved_name().and_then(|n| constants.get(n)) {
+                // Keep `2 k` from becoming the number `23`.
+                let is_grouped = result.last().is_some_and(is_operand);
+                push_number(&mut result, value, is_grouped);
+                continue;
+            }
+        }
+        result.push(item);
+    }
+    fold_brackets(result)
+}
+
+
+/// Folds the inside of every bracket first, dropping parentheses that only
+/// hold a number, and then the expression around them.
+fn fold_brackets(items: Vec<ExpressionItem>) -> Vec<ExpressionItem> {
+    let mut result = vec![];
+    let mut items = items.into_iter().peekable();
+    while let Some(item) = items.next() {
+        if !matches!(item, ExpressionItem::Other(Token::Symbol(Symbol::LeftParen | Symbol::LeftSquare | Symbol::LeftCurly))) {
+            result.push(item);
+            continue;
+        }
+        let mut inner = vec![];
+        let mut depth = 0;
+        let mut close = None;
+        for item in items.by_ref() {
+            match item {
+                ExpressionItem::Other(Token::Symbol(Symbol::LeftParen | Symbol::LeftSquare | Symbol::LeftCurly)) => depth += 1,
+                ExpressionItem::Other(Token::Symbol(Symbol::RightParen | Symbol::RightSquare | Symbol::RightCurly)) if depth == 0 => {
+                    close = Some(item);
+                    break;
+                },
+                ExpressionItem::Other(Token::Symbol(Symbol::RightParen | Symbol::RightSquare | Symbol::RightCurly)) => depth -= 1,
+                _ => (),
+            }
+            inner.push(item);
+        }
+        let Some(close) = close else {
+            // Unbalanced brackets are left for Desmos to report.
+            result.push(item);
+            result.append(&mut inner);
+            continue;
+        };
+        let mut inner = fold_brackets(inner);
+        let is_paren = matches!(item, ExpressionItem::Other(Token::Symbol(Symbol::LeftParen)));
+        // Parentheses next to an operand are a call or a product and stay.
+        let is_alone = !result.last().is_some_and(is_operand) && !items.peek().is_some_and(is_operand);
+        if is_paren && is_alone && matches!(inner.as_slice(), [ExpressionItem::Other(Token::Number(_))]) {
+            result.append(&mut inner);
+        } else {
+            result.push(item);
+            result.append(&mut inner);
+            result.push(close);
+        }
+    }
+    fold_segments(result)
+}
+
+
+/// Folds whole segments between separators, or else each of their terms.
+fn fold_segments(items: Vec<ExpressionItem>) -> Vec<ExpressionItem> {
+    let mut result = vec![];
+    let mut segment = vec![];
+    let mut depth = 0;
+    for item in items {
+        match &item {
+            ExpressionItem::Other(Token::Symbol(Symbol::LeftParen | Symbol::LeftSquare | Symbol::LeftCurly)) => depth += 1,
+            ExpressionItem::Other(Token::Symbol(Symbol::RightParen | Symbol::RightSquare | Symbol::RightCurly)) => depth -= 1,
+            ExpressionItem::Other(Token::Symbol(s)) if depth == 0 && SEPARATORS.contains(s) => {
+                result.append(&mut fold_segment(std::mem::take(&mut segment)));
+                result.push(item);
+                continue;
+            },
+            _ => (),
+        }
+        segment.push(item);
+    }
+    result.append(&mut fold_segment(segment));
+    result
+}
+
+
+fn fold_segment(items: Vec<ExpressionItem>) -> Vec<ExpressionItem> {
+    if let Some(value) = Evaluator::new(&items).evaluate() {
+        let mut result = vec![];
+        push_number(&mut result, value, false);
+        return result;
+    }
+    // Split at the '+' and '-' between terms, the ones following an operand.
+    let mut result = vec![];
+    let mut term = vec![];
+    let mut depth = 0;
+    let mut is_after_operand = false;
+    for item in items {
+        let is_sign = matches!(item, ExpressionItem::Other(Token::Symbol(Symbol::Add | Symbol::Sub)));
+        if depth == 0 && is_sign && is_after_operand {
+            fold_term(std::mem::take(&mut term), &mut result);
+            result.push(item);
+            is_after_operand = false;
+            continue;
+        }
+        match &item {
+            ExpressionItem::Other(Token::Symbol(Symbol::LeftParen | Symbol::LeftSquare | Symbol::LeftCurly)) => depth += 1,
+            ExpressionItem::Other(Token::Symbol(Symbol::RightParen | Symbol::RightSquare | Symbol::RightCurly)) => depth -= 1,
+            _ => (),
+        }
+        is_after_operand = is_operand(&item);
+        term.push(item);
+    }
+    fold_term(term, &mut result);
+    result
+}
+
+
+fn fold_term(items: Vec<ExpressionItem>, result: &mut Vec<ExpressionItem>) {
+    match Evaluator::new(&items).evaluate() {
+        Some(value) => push_number(result, value, false),
+        None => result.extend(items),
+    }
+}
+
+
+/// Whether an item ends an operand, so a '+' or '-' after it is binary and a
+/// number or bracket after it is multiplied.
+fn is_operand(item: &ExpressionItem) -> bool {
+    match item {
+        ExpressionItem::Variable(_) => true,
+        ExpressionItem::Other(Token::Number(_)) => true,
+        ExpressionItem::Other(Token::Symbol(s)) => matches!(s,
+            Symbol::RightParen | Symbol::RightSquare | Symbol::RightCurly | Symbol::Bang
+            | Symbol::X | Symbol::Y | Symbol::Theta | Symbol::Radius | Symbol::Vertical | Symbol::Horizontal
+        ),
+        _ => false,
+    }
+}
+
+
+fn push_number(result: &mut Vec<ExpressionItem>, value: f64, is_grouped: bool) {
+    let number = ExpressionItem::Other(Token::Number(value.abs().to_string()));
+    if is_grouped || value < 0.0 && result.last().is_some_and(is_operand) {
+        result.push(ExpressionItem::Other(Token::Symbol(Symbol::LeftParen)));
+        if value < 0.0 {
+            result.push(ExpressionItem::Other(Token::Symbol(Symbol::Sub)));
+        }
+        result.push(number);
+        result.push(ExpressionItem::Other(Token::Symbol(Symbol::RightParen)));
+    } else if value >= 0.0 {
+        result.push(number);
+    } else if let Some(ExpressionItem::Other(Token::Symbol(sign @ (Symbol::Add | Symbol::Sub)))) = result.last_mut() {
+        // `a + -2` reads better as `a - 2`.
+        *sign = if *sign == Symbol::Add { Symbol::Sub } else { Symbol::Add };
+        result.push(number);
+    } else {
+        result.push(ExpressionItem::Other(Token::Symbol(Symbol::Sub)));
+        result.push(number);
+    }
+}
+
+
+/// Evaluates arithmetic on numbers, `std.pi`, `std.e`, `std.tau`, `std.abs`
+/// and `std.floor`, failing on anything else.
+struct Evaluator<'a> {
+    items: &'a [ExpressionItem],
+    index: usize,
+} impl<'a> Evaluator<'a> {
+    fn new(items: &'a [ExpressionItem]) -> Self {
+        Self { items, index: 0 }
+    }
+    fn evaluate(&mut self) -> Option<f64> {
+        let value = self.sum()?;
+        if self.index == self.items.len() && value.is_finite() {
+            Some(value)
+        } else {
+            None
+        }
+    }
+    fn sum(&mut self) -> Option<f64> {
+        let mut value = self.product()?;
+        loop {
+            match self.peek_symbol() {
+                Some(Symbol::Add) => {
+                    self.index += 1;
+                    value += self.product()?;
+                },
+                Some(Symbol::Sub) => {
+                    self.index += 1;
+                    value -= self.product()?;
+                },
+                _ => return Some(value),
+            }
+        }
+    }
+    fn product(&mut self) -> Option<f64> {
+        let mut value = self.unary()?;
+        loop {
+            match self.peek_symbol() {
+                Some(Symbol::Mul) => {
+                    self.index += 1;
+                    value *= self.unary()?;
+                },
+                Some(Symbol::Div) => {
+                    self.index += 1;
+                    value /= self.unary()?;
+                },
+                // Desmos multiplies by a bracket or name right after a number, like `2std.pi`.
+                Some(Symbol::LeftParen) => value *= self.unary()?,
+                None if matches!(self.items.get(self.index), Some(ExpressionItem::Variable(_))) => value *= self.unary()?,
+                _ => return Some(value),
+            }
+        }
+    }
+    fn unary(&mut self) -> Option<f64> {
+        match self.peek_symbol() {
+            Some(Symbol::Sub) => {
+                self.index += 1;
+                Some(-self.unary()?)
+            },
+            Some(Symbol::Add) => {
+                self.index += 1;
+                self.unary()
+            },
+            _ => self.primary(),
+        }
+    }
+    fn primary(&mut self) -> Option<f64> {
+        let item = self.items.get(self.index)?;
+        self.index += 1;
+        match item {
+            ExpressionItem::Other(Token::Number(n)) => n.parse().ok(),
+            ExpressionItem::Other(Token::Symbol(Symbol::LeftParen)) => self.group(),
+            ExpressionItem::Variable(Variable::Std(name)) => match name.as_str() {
+                "pi" => Some(std::f64::consts::PI),
+                "tau" => Some(std::f64::consts::TAU),
+                "e" => Some(std::f64::consts::E),
+                "abs" => Some(self.argument()?.abs()),
+                "floor" => Some(self.argument()?.floor()),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+    fn argument(&mut self) -> Option<f64> {
+        if self.peek_symbol() != Some(Symbol::LeftParen) {
+            return None;
+        }
+        self.index += 1;
+        self.group()
+    }
+    fn group(&mut self) -> Option<f64> {
+        let value = self.sum()?;
+        if self.peek_symbol() != Some(Symbol::RightParen) {
+            return None;
+        }
+        self.index += 1;
+        Some(value)
+    }
+    fn peek_symbol(&self) -> Option<Symbol> {
+        match self.items.get(self.index) {
+            Some(ExpressionItem::Other(Token::Symbol(s))) => Some(*s),
+            _ => None,
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::ast::constants::fold_constants;
+    use crate::ast::lexer::Token;
+    use crate::ast::uses::resolve_names;
+    use crate::ast::{AbstractSyntaxItem, AbstractSyntaxTree, AbstractSyntaxTreeTrait, ExpressionItem, Variable};
+
+    /// Folds `source` and writes each expression back out, with variables by name.
+    fn fold(source: &str) -> Result<Vec<String>, String> {
+        let tokens = Token::vec_from_chars(&mut source.chars().peekable())?;
+        let mut ast = AbstractSyntaxTree::from_tokens(&mut tokens.iter().peekable(), false)?;
+        resolve_names(&mut ast).map_err(|e| e.to_string())?;
+        fold_constants(&mut ast).map_err(|e| e.to_string())?;
+        let mut result = vec![];
+        write_expressions(&ast, &mut result);
+        Ok(result)
+    }
+
+    fn write_expressions(ast: &AbstractSyntaxTree, result: &mut Vec<String>) {
+        for item in ast {
+            match item {
+                AbstractSyntaxItem::Expression(items, _) => result.push(items.iter().map(|item| match item {
+                    ExpressionItem::Variable(Variable::Relative(names) | Variable::Absolute(names)) => names.join("."),
+                    ExpressionItem::Variable(Variable::Std(name)) => format!("std.{name}"),
+                    ExpressionItem::Other(token) => token.get_latex(),
+                }).collect()),
+                AbstractSyntaxItem::Namespace(_, items, _) => write_expressions(items, result),
+                _ => (),
+            }
+        }
+    }
+
+    #[test]
+    fn folds_with_precedence() {
+        assert_eq!(fold("this.y = 2 - 3 * 4;").unwrap(), ["y=-10"]);
+        assert_eq!(fold("this.y = x * 2 + 3 * 4;").unwrap(), ["y=x\\cdot 2+12"]);
+        assert_eq!(fold("this.y = 2(3);").unwrap(), ["y=6"]);
+        assert_eq!(fold("this.y = -(2 + 3);").unwrap(), ["y=-5"]);
+    }
+
+    #[test]
+    fn flips_signs_of_negative_terms() {
+        assert_eq!(fold("this.y = x - -2;").unwrap(), ["y=x+2"]);
+        assert_eq!(fold("this.y = x + -2;").unwrap(), ["y=x-2"]);
+        assert_eq!(fold("const k = -2; this.y = x - k;").unwrap(), ["y=x+2"]);
+    }
+
+    #[test]
+    fn keeps_calls_and_trigonometry() {
+        assert_eq!(fold("this.y = x(3);").unwrap(), ["y=x\\left(3\\right)"]);
+        assert_eq!(fold("this.y = std.sin(std.pi / 2) + 2;").unwrap(), ["y=std.sin\\left(1.5707963267948966\\right)+2"]);
+    }
+
+    #[test]
+    fn inlines_constants() {
+        assert_eq!(fold("const k = 2; this.y = 3 k;").unwrap(), ["y=6"]);
+        assert_eq!(fold("const k = 2; this.y = x < k * 3;").unwrap(), ["y=x<6"]);
+        assert_eq!(fold("const a = 2; const b = a * a; this.y = b;").unwrap(), ["y=4"]);
+        assert_eq!(fold("namespace n { const this.k = 2; } this.y = n.k;").unwrap(), ["y=2"]);
+        assert_eq!(fold("const k = 1 / 0; this.y = k;").unwrap_err(), "Constant 'k' does not evaluate to a number");
+    }
+
+    #[test]
+    fn inlines_constants_declared_after_their_use() {
+        assert_eq!(fold("this.y = k + 1; const k = 2;").unwrap(), ["y=3"]);
+        assert_eq!(fold("this.y = m.k; namespace m { const this.k = 2; }").unwrap(), ["y=2"]);
+        assert_eq!(fold("const a = 2; this.y = b; const b = a * 3;").unwrap(), ["y=6"]);
+    }
+
+    #[test]
+    fn orders_constants_that_use_constants() {
+        assert_eq!(fold("const a = b; const b = 2;").unwrap_err(), "Constant 'a' uses 'b', which is declared after it");
+        assert_eq!(fold("const a = b; const b = a;").unwrap_err(), "Constant cycle: a -> b -> a");
+        assert_eq!(fold("const a = a + 1;").unwrap_err(), "Constant cycle: a -> a");
+    }
+}
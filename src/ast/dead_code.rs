@@ -0,0 +1,195 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::ast::lexer::{Symbol, Token};
+use crate::ast::{AbstractSyntaxItem, AbstractSyntaxTree, ExpressionItem, Variable};
+
+
+type Name = Vec<String>;
+
+
+/// Removes definitions that nothing on the graph depends on, and the
+/// namespaces left empty by that. Graphs, text, sliders (`this.r = 2;`),
+/// expressions that define nothing, expressions that are `visible` or have an
+/// `id`, and names listed in `export` are kept, along with every definition
+/// they reach. Names must have been resolved.
+pub fn eliminate_dead_code(ast: &mut AbstractSyntaxTree) {
+    let mut roots = vec![];
+    let mut dependencies = HashMap::new();
+    collect_dependencies(ast, &mut roots, &mut dependencies);
+    let mut reachable = HashSet::new();
+    while let Some(name) = roots.pop() {
+        if reachable.insert(name.to_vec()) {
+            roots.extend(dependencies.get(&name).into_iter().flatten().cloned());
+        }
+    }
+    retain_reachable(ast, &reachable);
+}
+
+
+/// The variable an expression like `this.x = ...` or `this.f(a) = ...` defines.
+pub fn get_definition(items: &[ExpressionItem]) -> Option<&Variable> {
+    let Some(ExpressionItem::Variable(variable)) = items.first() else {
+        return None;
+    };
+    let mut depth = 0;
+    for item in &items[1..] {
+        match item {
+            ExpressionItem::Other(Token::Symbol(Symbol::LeftParen)) => depth += 1,
+            ExpressionItem::Other(Token::Symbol(Symbol::RightParen)) => depth -= 1,
+            ExpressionItem::Other(Token::Symbol(Symbol::Equal)) if depth == 0 => return Some(variable),
+            _ if depth == 0 => return None,
+            _ => (),
+        }
+    }
+    None
+}
+
+
+/// Whether an expression is a number Desmos shows as a slider, like `this.r = -2;`.
+pub fn is_slider(items: &[ExpressionItem]) -> bool {
+    matches!(items, [
+        ExpressionItem::Variable(_),
+        ExpressionItem::Other(Token::Symbol(Symbol::Equal)),
+        ExpressionItem::Other(Token::Number(_)),
+    ] | [
+        ExpressionItem::Variable(_),
+        ExpressionItem::Other(Token::Symbol(Symbol::Equal)),
+        ExpressionItem::Other(Token::Symbol(Symbol::Sub)),
+        ExpressionItem::Other(Token::Number(_)),
+    ])
+}
+
+
+fn get_references(items: &[ExpressionItem]) -> Vec<Name> {
+    items.iter().filter_map(|i| i.get_resolved_name().cloned()).collect()
+}
+
+
+fn collect_dependencies(ast: &AbstractSyntaxTree, roots: &mut Vec<Name>, dependencies: &mut HashMap<Name, Vec<Name>>) {
+    type T = AbstractSyntaxItem;
+    for item in ast {
+        match item {
+            T::Expression(items, attributes) => {
+                let references = get_references(items);
+                match get_definition(items).and_then(Variable::get_resolved_name) {
+                    Some(name) if !is_slider(items) => {
+                        // A visible expression is drawn, and scripts can look up one with an id.
+                        if attributes.id.is_some() || attributes.hidden == Some(false) {
+                            roots.push(name.to_vec());
+                        }
+                        dependencies.entry(name.to_vec()).or_default().extend(references);
+                    },
+                    _ => roots.extend(references),
+                }
+            },
+            T::Graph(color, _, items, _) => {
+                roots.extend(get_references(items));
+                roots.extend(get_references(color.as_slice()));
+            },
+            T::Export(variable) => roots.extend(variable.get_resolved_name().cloned()),
+            T::Namespace(_, items, _) => collect_dependencies(items, roots, dependencies),
+            _ => (),
+        }
+    }
+}
+
+
+fn retain_reachable(ast: &mut AbstractSyntaxTree, reachable: &HashSet<Name>) {
+    type T = AbstractSyntaxItem;
+    ast.retain_mut(|item| match item {
+        T::Expression(items, _) => {
+            let name = get_definition(items).and_then(Variable::get_resolved_name);
+            is_slider(items) || name.is_none_or(|n| reachable.contains(n))
+        },
+        T::Namespace(_, items, _) => {
+            retain_reachable(items, reachable);
+            items.iter().any(|i| matches!(i, T::Expression(..) | T::Graph(..) | T::Text(_) | T::Namespace(..)))
+        },
+        _ => true,
+    });
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::ast::dead_code::{eliminate_dead_code, get_definition};
+    use crate::ast::lexer::Token;
+    use crate::ast::uses::resolve_names;
+    use crate::ast::{AbstractSyntaxItem, AbstractSyntaxTree, AbstractSyntaxTreeTrait, Variable};
+    use crate::{compile, DesmosLine, Options};
+
+    /// The names of what is left of `source`, with `graph` for graphs and
+    /// namespaces written around their contents.
+    fn remaining(source: &str) -> Vec<String> {
+        let tokens = Token::vec_from_chars(&mut source.chars().peekable()).unwrap();
+        let mut ast = AbstractSyntaxTree::from_tokens(&mut tokens.iter().peekable(), false).unwrap();
+        resolve_names(&mut ast).unwrap();
+        eliminate_dead_code(&mut ast);
+        let mut result = vec![];
+        write_names(&ast, &mut result);
+        result
+    }
+
+    fn write_names(ast: &AbstractSyntaxTree, result: &mut Vec<String>) {
+        for item in ast {
+            match item {
+                AbstractSyntaxItem::Expression(items, _) => match get_definition(items) {
+                    Some(Variable::Relative(names) | Variable::Absolute(names)) => result.push(names.join(".")),
+                    _ => result.push("expression".to_string()),
+                },
+                AbstractSyntaxItem::Graph(..) => result.push("graph".to_string()),
+                AbstractSyntaxItem::Namespace(name, items, _) => {
+                    result.push(format!("{name} {{"));
+                    write_names(items, result);
+                    result.push("}".to_string());
+                },
+                _ => (),
+            }
+        }
+    }
+
+    #[test]
+    fn keeps_sliders_and_expressions_without_names() {
+        assert_eq!(remaining("this.r = 2; this.s = -1; this.f(x) = x;"), ["r", "s"]);
+        assert_eq!(remaining("this.a = 1 + 1; this.b = 2 + 2; this.a + 2;"), ["a", "expression"]);
+    }
+
+    #[test]
+    fn keeps_everything_a_graph_reaches() {
+        let source = "this.a = 1 + 1; this.b = a + 1; this.c = b + 1; this.d = c + 1; graph: @y = c;";
+        assert_eq!(remaining(source), ["a", "b", "c", "graph"]);
+        let source = "namespace n { this.f(x) = x; } namespace m { this.g(x) = n.f(x); } graph: @y = m.g(@x);";
+        assert_eq!(remaining(source), ["n {", "n.f", "}", "m {", "m.g", "}", "graph"]);
+        let source = "namespace n { this.f(x) = x; this.g(x) = x; } use n.f; graph: @y = f(@x);";
+        assert_eq!(remaining(source), ["n {", "n.f", "}", "graph"]);
+    }
+
+    #[test]
+    fn keeps_exports() {
+        let source = "namespace n { this.speed = 1 + 1; this.f(x) = x; } export n.speed;";
+        assert_eq!(remaining(source), ["n {", "n.speed", "}"]);
+    }
+
+    #[test]
+    fn drops_empty_namespaces() {
+        let source = "namespace n { this.f(x) = x; } namespace m { namespace k { this.h(x) = x; } } graph: @y = n.f(@x);";
+        assert_eq!(remaining(source), ["n {", "n.f", "}", "graph"]);
+    }
+
+    #[test]
+    fn keep_unused_skips_elimination() {
+        let count = |eliminate_dead_code| {
+            let options = Options { eliminate_dead_code, ..Options::default() };
+            let output = compile("this.f(x) = x; this.g(x) = x; graph: @y = f(@x);", &options).unwrap();
+            output.calculator.expressions.iter().filter(|line| matches!(line, DesmosLine::Expression(_))).count()
+        };
+        assert_eq!(count(true), 2);
+        assert_eq!(count(false), 3);
+    }
+
+    #[test]
+    fn keeps_expressions_with_ids_or_drawn() {
+        let source = "@[id = \"k\"]\nthis.k = 1 + 1;\n@[visible]\nthis.v = 1 + 1;\nthis.u = 1 + 1;";
+        assert_eq!(remaining(source), ["k", "v"]);
+    }
+}
@@ -51,7 +51,7 @@ struct SymbolTree {
 }
 
 
-#[derive(Debug,Copy,Clone)]
+#[derive(Debug,Copy,Clone,PartialEq)]
 pub enum Symbol {
     Add,
     Sub,
@@ -77,6 +77,7 @@ pub enum Symbol {
     Comma,
     Colon,
     Elipsis,
+    Range,
     Vertical,
     Horizontal,
     Semicolon,
@@ -86,6 +87,7 @@ pub enum Symbol {
     RightSquare,
     LeftCurly,
     RightCurly,
+    Attributes,
 } impl Symbol {
     pub fn from_chars<I>(chars: &mut Peekable<I>) -> Result<Self, &'static str>
     where I: Iterator<Item = char> 
@@ -116,6 +118,7 @@ pub enum Symbol {
                 (",", Self::Comma),
                 (":", Self::Colon),
                 ("...", Self::Elipsis),
+                ("..", Self::Range),
                 ("~y", Self::Vertical),
                 ("~x", Self::Horizontal),
                 (";", Self::Semicolon),
@@ -125,6 +128,7 @@ pub enum Symbol {
                 ("]", Self::RightSquare),
                 ("{", Self::LeftCurly),
                 ("}", Self::RightCurly),
+                ("@[", Self::Attributes),
             ]
         ).symbol_from_chars(chars)
     }
@@ -153,7 +157,7 @@ pub enum Symbol {
             Self::Arrow => "\\to ",
             Self::Comma => ",",
             Self::Colon => ":",
-            Self::Elipsis => "...",
+            Self::Elipsis | Self::Range => "...",
             Self::Vertical => ".y",
             Self::Horizontal => ".x",
             Self::Semicolon => ";",
@@ -163,6 +167,7 @@ pub enum Symbol {
             Self::RightSquare => "\\right]",
             Self::LeftCurly => "\\left\\{",
             Self::RightCurly => "\\right\\}",
+            Self::Attributes => "",
         })
     }
 }
@@ -175,6 +180,11 @@ pub enum Keyword {
     This,
     Std,
     Use,
+    Import,
+    For,
+    Const,
+    Export,
+    Pub,
 } impl Keyword {
     pub fn from_string(string: String) -> Option<Self> {
         match string.as_str() {
@@ -183,6 +193,11 @@ pub enum Keyword {
             "this" => Some(Self::This),
             "std" => Some(Self::Std),
             "use" => Some(Self::Use),
+            "import" => Some(Self::Import),
+            "for" => Some(Self::For),
+            "const" => Some(Self::Const),
+            "export" => Some(Self::Export),
+            "pub" => Some(Self::Pub),
             _ => None
         }
     }
@@ -196,6 +211,7 @@ pub enum Token {
     Identifier(String),
     Number(String),
     Text(String),
+    String(String),
     Keyword(Keyword),
 } impl Token {
     pub fn from_ref(token: &Self) -> Self {
@@ -204,6 +220,7 @@ pub enum Token {
             Self::Identifier(i) => Self::Identifier(i.to_string()),
             Self::Number(n) => Self::Number(n.to_string()),
             Self::Text(t) => Self::Text(t.to_string()),
+            Self::String(s) => Self::String(s.to_string()),
             Self::Keyword(k) => Self::Keyword(*k),
             Self::Whitespace(b) => Self::Whitespace(*b),
         }
@@ -212,21 +229,24 @@ pub enum Token {
     where I: Iterator<Item = char>
     {
         let mut result = vec![];
-        while let Some(&c) = chars.peek() {
-            if c.is_whitespace() {
-                result.push(Self::whitespace_from_chars(chars)?);
-            } else if c.is_alphabetic() || c == '_' {
-                result.push(Self::identifier_or_keyword_from_chars(chars)?);
-            } else if c.is_numeric() {
-                result.push(Self::number_from_chars(chars)?);
-            } else if c == '#' {
-                result.push(Self::text_from_chars(chars)?);
-            } else {
-                result.push(Self::symbol_from_chars(chars)?);
-            }
+        while chars.peek().is_some() {
+            result.push(Self::from_chars(chars)?);
         }
         Ok(result)
     }
+    /// Reads the single token starting at the next char.
+    pub fn from_chars<I>(chars: &mut Peekable<I>) -> Result<Self, &'static str>
+    where I: Iterator<Item = char>
+    {
+        match chars.peek() {
+            Some(c) if c.is_whitespace() => Self::whitespace_from_chars(chars),
+            Some(&c) if c.is_alphabetic() || c == '_' => Self::identifier_or_keyword_from_chars(chars),
+            Some(c) if c.is_numeric() => Self::number_from_chars(chars),
+            Some('#') => Self::text_from_chars(chars),
+            Some('"') => Self::string_from_chars(chars),
+            _ => Self::symbol_from_chars(chars),
+        }
+    }
     pub fn text_from_chars<I>(chars: &mut Peekable<I>) -> Result<Self, &'static str>
     where I: Iterator<Item = char>
     {
@@ -254,7 +274,7 @@ pub enum Token {
                         break; 
                     }
                 }
-                while let Some(c) = chars.next() {
+                for c in chars.by_ref() {
                     if c == '\n' {
                         break;
                     } else {
@@ -267,6 +287,22 @@ pub enum Token {
         }
         Ok(Self::Text(value))
     }
+    /// Reads a string like `"Control panel"`, where `\"` and `\\` stand for `"` and `\`.
+    pub fn string_from_chars<I>(chars: &mut Peekable<I>) -> Result<Self, &'static str>
+    where I: Iterator<Item = char>
+    {
+        chars.next();
+        let mut value = String::new();
+        while let Some(c) = chars.next() {
+            match c {
+                '"' => return Ok(Self::String(value)),
+                '\\' => value.push(chars.next().ok_or("Unclosed string")?),
+                '\n' => break,
+                _ => value.push(c),
+            }
+        }
+        Err("Unclosed string")
+    }
     pub fn symbol_from_chars<I>(chars: &mut Peekable<I>) -> Result<Self, &'static str>
     where I: Iterator<Item = char>
     {
@@ -314,7 +350,11 @@ pub enum Token {
                 value.push(c);
                 chars.next();
             } else if c == '.' {
-                if is_decimal {
+                if value.ends_with('.') {
+                    // A second '.' starts a range like `0..10`, which leaves a lone '.' to lex.
+                    value.pop();
+                    break;
+                } else if is_decimal {
                     return Err("Unexpected '.'");
                 }
                 value.push(c);
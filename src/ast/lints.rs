@@ -0,0 +1,280 @@
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+
+use crate::ast::dead_code::{get_definition, is_slider};
+use crate::ast::uses::Usespace;
+use crate::ast::{AbstractSyntaxItem, AbstractSyntaxTree, ExpressionItem, Variable};
+use crate::Location;
+
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Lint {
+    UnusedDefinition,
+    UnusedUse,
+    ShadowedUse,
+    EmptyNamespace,
+    GraphWithoutColor,
+} impl Lint {
+    pub fn from_string(string: &str) -> Option<Self> {
+        Self::get_all().into_iter().find(|l| l.get_name() == string)
+    }
+    pub fn get_name(&self) -> &'static str {
+        match self {
+            Self::UnusedDefinition => "unused-definition",
+            Self::UnusedUse => "unused-use",
+            Self::ShadowedUse => "shadowed-use",
+            Self::EmptyNamespace => "empty-namespace",
+            Self::GraphWithoutColor => "graph-without-color",
+        }
+    }
+    pub fn get_all() -> [Self; 5] {
+        [Self::UnusedDefinition, Self::UnusedUse, Self::ShadowedUse, Self::EmptyNamespace, Self::GraphWithoutColor]
+    }
+}
+
+
+/// What to do when a lint finds something. Lints warn unless configured otherwise.
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub enum Level {
+    Allow,
+    #[default]
+    Warn,
+    Deny,
+}
+
+
+struct UseRecord {
+    keys: Vec<String>,
+    path: String,
+    is_used: bool,
+    location: Option<Location>,
+}
+
+
+/// Runs every lint over a linked tree and returns what each of them found.
+/// Libraries are there to be used in part, so unused definitions are not
+/// reported for `pub` members or for names in `libraries`, like the
+/// namespaces of imported modules and what included files define.
+pub fn lint(ast: &AbstractSyntaxTree, libraries: &[Vec<String>]) -> Vec<(Lint, String, Option<Location>)> {
+    let mut linter = Linter::default();
+    linter.walk(ast, &[], &mut Usespace::default());
+    let mut seen = HashSet::new();
+    for (name, location) in &linter.definitions {
+        let is_library = libraries.iter().any(|l| name.starts_with(l));
+        if seen.insert(name) && !is_library && !linter.references.contains(name) && !linter.exports.contains(name) {
+            let message = format!("Definition '{}' is never used", name.join("."));
+            linter.findings.push((Lint::UnusedDefinition, message, location.clone()));
+        }
+    }
+    linter.findings
+}
+
+
+/// The names defined by the items that come from other files than `path`,
+/// like the ones an include brings in. An item belongs to the file its first
+/// line is in, so an include in the middle of an expression does not count.
+pub fn get_included_definitions(ast: &AbstractSyntaxTree, path: &Path) -> Vec<Vec<String>> {
+    let mut result = vec![];
+    collect_included_definitions(ast, &[], path, &mut false, &mut result);
+    result
+}
+
+
+fn collect_included_definitions(ast: &AbstractSyntaxTree, namespaces: &[String], path: &Path, is_included: &mut bool, result: &mut Vec<Vec<String>>) {
+    type T = AbstractSyntaxItem;
+    let usespace = HashMap::new();
+    for item in ast {
+        let name = match item {
+            T::Location(location) => {
+                *is_included = location.path != path;
+                None
+            },
+            T::Expression(items, _) => get_definition(items).and_then(|v| v.get_name(namespaces.to_vec(), &usespace)),
+            T::Const(variable, _) => variable.get_variable_name(namespaces.to_vec(), &usespace),
+            T::Namespace(name, items, _) => {
+                let mut names = namespaces.to_vec();
+                names.push(name.to_string());
+                let is_namespace_included = *is_included;
+                collect_included_definitions(items, &names, path, is_included, result);
+                *is_included = is_namespace_included;
+                None
+            },
+            _ => None,
+        };
+        if *is_included {
+            result.extend(name);
+        }
+    }
+}
+
+
+#[derive(Default)]
+struct Linter {
+    findings: Vec<(Lint, String, Option<Location>)>,
+    /// Where the item being linted is.
+    location: Option<Location>,
+    /// The `use`s in scope, innermost last.
+    uses: Vec<UseRecord>,
+    definitions: Vec<(Vec<String>, Option<Location>)>,
+    references: HashSet<Vec<String>>,
+    /// Names that are exported, visible, have an id or are `pub`.
+    exports: HashSet<Vec<String>>,
+} impl Linter {
+    fn walk(&mut self, ast: &AbstractSyntaxTree, namespaces: &[String], usespace: &mut Usespace) {
+        type T = AbstractSyntaxItem;
+        let first_use = self.uses.len();
+        let mark = usespace.get_mark();
+        let scope = if namespaces.is_empty() { String::new() } else { format!(" in '{}'", namespaces.join(".")) };
+        let start = self.location.clone();
+        for item in ast {
+            if let T::Location(location) = item {
+                self.location = Some(location.clone());
+            }
+            let T::Use(ExpressionItem::Variable(variable), binding) = item else {
+                continue;
+            };
+            let path = binding.format(variable);
+            let bindings = variable.get_use_bindings(binding, namespaces, usespace.get_bindings());
+            let mut shadowed = vec![];
+            for (key, _) in &bindings {
+                if let Some(earlier) = self.uses[..first_use].iter().rev().find(|u| u.keys.contains(key)) {
+                    if !shadowed.contains(&earlier.path) {
+                        shadowed.push(earlier.path.to_string());
+                    }
+                }
+            }
+            for earlier in shadowed {
+                self.find(Lint::ShadowedUse, format!("'use {path};' shadows 'use {earlier};'{scope}"));
+            }
+            let keys = bindings.iter().map(|(k, _)| k.to_string()).collect();
+            self.uses.push(UseRecord { keys, path, is_used: false, location: self.location.clone() });
+            for (key, value) in bindings {
+                usespace.bind(key, value);
+            }
+        }
+        self.location = start;
+        for item in ast {
+            match item {
+                T::Location(location) => self.location = Some(location.clone()),
+                T::Expression(items, attributes) => {
+                    let definition = get_definition(items).and_then(|v| v.get_name(namespaces.to_vec(), usespace.get_bindings()));
+                    if let Some(name) = &definition {
+                        if !is_slider(items) {
+                            self.definitions.push((name.to_vec(), self.location.clone()));
+                        }
+                        if attributes.id.is_some() || attributes.hidden == Some(false) {
+                            self.exports.insert(name.to_vec());
+                        }
+                    }
+                    // The name being defined is not a use of it.
+                    let skip = usize::from(definition.is_some());
+                    self.add_references(&items[..skip], namespaces, usespace, false);
+                    self.add_references(&items[skip..], namespaces, usespace, true);
+                },
+                T::Graph(color, _, items, _) => {
+                    if color.is_none() {
+                        self.find(Lint::GraphWithoutColor, format!("Graph without a colour{scope}"));
+                    }
+                    self.add_references(color.as_slice(), namespaces, usespace, true);
+                    self.add_references(items, namespaces, usespace, true);
+                },
+                T::Const(variable, items) => {
+                    self.add_references(std::slice::from_ref(variable), namespaces, usespace, false);
+                    if let Some(name) = variable.get_variable_name(namespaces.to_vec(), usespace.get_bindings()) {
+                        self.definitions.push((name, self.location.clone()));
+                    }
+                    self.add_references(items, namespaces, usespace, true);
+                },
+                T::Public(variable) => {
+                    self.exports.extend(variable.get_variable_name(namespaces.to_vec(), usespace.get_bindings()));
+                },
+                T::Export(variable) => {
+                    self.add_references(std::slice::from_ref(variable), namespaces, usespace, false);
+                    self.exports.extend(variable.get_variable_name(namespaces.to_vec(), usespace.get_bindings()));
+                },
+                T::Namespace(name, items, attributes) => {
+                    let mut names = namespaces.to_vec();
+                    names.push(name.to_string());
+                    if items.iter().all(|i| matches!(i, T::Use(..) | T::TokensLeft(_) | T::Location(_))) {
+                        self.find(Lint::EmptyNamespace, format!("Namespace '{}' is empty", names.join(".")));
+                    }
+                    if attributes.is_module {
+                        // A module neither sees nor uses the `use`s around it.
+                        let outer = std::mem::take(&mut self.uses);
+                        self.walk(items, &names, &mut Usespace::default());
+                        self.uses = outer;
+                    } else {
+                        self.walk(items, &names, usespace);
+                    }
+                },
+                _ => (),
+            }
+        }
+        for record in self.uses.drain(first_use..) {
+            if !record.is_used {
+                self.findings.push((Lint::UnusedUse, format!("'use {};' is never used{scope}", record.path), record.location));
+            }
+        }
+        usespace.leave(mark);
+    }
+    fn find(&mut self, lint: Lint, message: String) {
+        self.findings.push((lint, message, self.location.clone()));
+    }
+    /// Marks the `use`s the variables go through, and records what they refer to.
+    fn add_references(&mut self, items: &[ExpressionItem], namespaces: &[String], usespace: &Usespace, is_reference: bool) {
+        for item in items {
+            if let ExpressionItem::Variable(Variable::Absolute(names)) = item {
+                let head = names.first();
+                if let Some(record) = self.uses.iter_mut().rev().find(|u| head.is_some_and(|h| u.keys.contains(h))) {
+                    record.is_used = true;
+                }
+            }
+            if is_reference {
+                self.references.extend(item.get_variable_name(namespaces.to_vec(), usespace.get_bindings()));
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::preprocessor::files::MemoryFileProvider;
+    use crate::{compile, Options};
+
+    /// The warnings of compiling "main.dmn" next to the other `files`.
+    fn warnings(files: &[(&str, &str)]) -> Vec<String> {
+        let mut provider = MemoryFileProvider::new();
+        for (path, contents) in files {
+            provider.insert(path, contents);
+        }
+        let options = Options { path: "main.dmn".into(), provider: Arc::new(provider), ..Options::default() };
+        let source = options.read_source().unwrap();
+        let output = compile(&source, &options).unwrap_or_else(|e| panic!("{e}"));
+        output.warnings.0.iter().map(|w| w.to_string()).collect()
+    }
+
+    #[test]
+    fn reports_findings_where_they_are() {
+        let source = "use a.x;\nnamespace a { this.x = 1; }\nnamespace e {\n    use a.x;\n}\ngraph: @y = @x;\nthis.f(t) = t;\n";
+        assert_eq!(warnings(&[("main.dmn", source)]), [
+            "warning: main.dmn:3: Namespace 'e' is empty [empty-namespace]",
+            "warning: main.dmn:4: 'use a.x;' shadows 'use a.x;' in 'e' [shadowed-use]",
+            "warning: main.dmn:4: 'use a.x;' is never used in 'e' [unused-use]",
+            "warning: main.dmn:6: Graph without a colour [graph-without-color]",
+            "warning: main.dmn:1: 'use a.x;' is never used [unused-use]",
+            "warning: main.dmn:7: Definition 'f' is never used [unused-definition]",
+        ]);
+    }
+
+    #[test]
+    fn skips_definitions_included_into_namespaces() {
+        let warnings = warnings(&[
+            ("main.dmn", "namespace g {\n/#lib.dmn\n}\nnamespace h {\n    this.f(t) = t;\n}\n"),
+            ("lib.dmn", "namespace lib {\n    this.f(t) = t;\n}\n"),
+        ]);
+        assert_eq!(warnings, ["warning: main.dmn:5: Definition 'h.f' is never used [unused-definition]"]);
+    }
+}
@@ -0,0 +1,355 @@
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+use crate::ast::dead_code::get_definition;
+use crate::ast::{AbstractSyntaxItem, AbstractSyntaxTree, ExpressionItem, UseBinding, Variable};
+use crate::{Diagnostic, Location};
+
+
+type Name = Vec<String>;
+
+
+/// The names bound by the `use`s of the blocks being walked. A block's `use`s
+/// apply to all of it, including the namespaces inside it other than imported
+/// modules, and leaving the block brings back whatever they shadowed.
+#[derive(Default)]
+pub struct Usespace {
+    bindings: HashMap<String, Variable>,
+    /// Each name bound so far, with what it was bound to before.
+    shadowed: Vec<(String, Option<Variable>)>,
+} impl Usespace {
+    pub fn get_bindings(&self) -> &HashMap<String, Variable> {
+        &self.bindings
+    }
+    /// Where the current block starts, to `leave` it later.
+    pub fn get_mark(&self) -> usize {
+        self.shadowed.len()
+    }
+    pub fn bind(&mut self, key: String, value: Variable) {
+        let previous = self.bindings.insert(key.to_string(), value);
+        self.shadowed.push((key, previous));
+    }
+    /// Binds every `use` of a block, in order so a `use` can build on an earlier one.
+    pub fn enter(&mut self, ast: &AbstractSyntaxTree, namespaces: &[String]) -> usize {
+        let mark = self.get_mark();
+        for item in ast {
+            if let AbstractSyntaxItem::Use(ExpressionItem::Variable(variable), binding) = item {
+                for (key, value) in variable.get_use_bindings(binding, namespaces, &self.bindings) {
+                    self.bind(key, value);
+                }
+            }
+        }
+        mark
+    }
+    pub fn leave(&mut self, mark: usize) {
+        for (key, previous) in self.shadowed.drain(mark..).rev() {
+            match previous {
+                Some(value) => self.bindings.insert(key, value),
+                None => self.bindings.remove(&key),
+            };
+        }
+    }
+}
+
+
+/// Lists the names each `use a.*;` brings into scope, and checks that no two
+/// `use`s in the same block bind a name to different things. Names bound
+/// explicitly in a block take precedence over the ones a `*` would bind.
+pub fn expand_uses(ast: &mut AbstractSyntaxTree) -> Result<(), Diagnostic> {
+    let mut members = Members::default();
+    members.collect(ast, &[]);
+    expand_tree(ast, &[], &mut Usespace::default(), &members)
+}
+
+
+/// Replaces every name with what it stands for, a full name or a standard
+/// library name, and drops the `use`s. The passes after this one, and the
+/// ids and LaTeX of the calculator, all work on these resolved names.
+/// Private members can only be used from inside their namespace.
+pub fn resolve_names(ast: &mut AbstractSyntaxTree) -> Result<(), Diagnostic> {
+    let mut members = Members::default();
+    members.collect(ast, &[]);
+    resolve_tree(ast, &[], &mut Usespace::default(), &members)
+}
+
+
+#[derive(Default)]
+struct Members {
+    /// Every name that can be used: definitions, constants and namespaces.
+    names: HashSet<Name>,
+    /// The members of namespaces with `pub` members that are not `pub` themselves.
+    private: HashSet<Name>,
+} impl Members {
+    fn collect(&mut self, ast: &AbstractSyntaxTree, namespaces: &[String]) {
+        type T = AbstractSyntaxItem;
+        let usespace = HashMap::new();
+        let public: Vec<Name> = ast.iter().filter_map(|item| match item {
+            T::Public(variable) => variable.get_variable_name(namespaces.to_vec(), &usespace),
+            _ => None,
+        }).collect();
+        for item in ast {
+            let name = match item {
+                T::Expression(items, _) => get_definition(items).and_then(|v| v.get_name(namespaces.to_vec(), &usespace)),
+                T::Const(variable, _) => variable.get_variable_name(namespaces.to_vec(), &usespace),
+                T::Namespace(name, items, _) => {
+                    let mut names = namespaces.to_vec();
+                    names.push(name.to_string());
+                    self.collect(items, &names);
+                    Some(names)
+                },
+                _ => None,
+            };
+            let Some(name) = name else {
+                continue;
+            };
+            // Without any `pub`, everything in a namespace is public.
+            let is_member = name.len() == namespaces.len() + 1 && name.starts_with(namespaces);
+            if is_member && !public.is_empty() && !public.contains(&name) {
+                self.private.insert(name.to_vec());
+            }
+            self.names.insert(name);
+        }
+    }
+    /// Whether code in `namespaces` can use `name`: it has to be inside the
+    /// namespace of every private name `name` goes through.
+    fn check_visibility(&self, name: &[String], namespaces: &[String]) -> Result<(), String> {
+        for end in 1..=name.len() {
+            let member = &name[..end];
+            let owner = &member[..end - 1];
+            if !self.private.contains(member) || namespaces.starts_with(owner) {
+                continue;
+            }
+            let place = if namespaces.is_empty() { "the top level".to_string() } else { format!("'{}'", namespaces.join(".")) };
+            return Err(if end == name.len() {
+                format!("'{}' is private to '{}' and cannot be used from {place}", member.join("."), owner.join("."))
+            } else {
+                format!("'{}' cannot be used from {place}, '{}' is private to '{}'", name.join("."), member.join("."), owner.join("."))
+            });
+        }
+        Ok(())
+    }
+    fn is_visible(&self, name: &[String], namespaces: &[String]) -> bool {
+        self.check_visibility(name, namespaces).is_ok()
+    }
+}
+
+
+fn expand_tree(ast: &mut AbstractSyntaxTree, namespaces: &[String], usespace: &mut Usespace, members: &Members) -> Result<(), Diagnostic> {
+    type T = AbstractSyntaxItem;
+    let explicit: HashSet<String> = ast.iter().filter_map(|item| match item {
+        T::Use(ExpressionItem::Variable(variable), binding) => variable.get_use_key(binding),
+        _ => None,
+    }).collect();
+    let mut bound: HashMap<String, (String, Variable)> = HashMap::new();
+    let mark = usespace.get_mark();
+    let mut location = None;
+    for item in ast.iter_mut() {
+        if let T::Location(l) = item {
+            location = Some(l.clone());
+        }
+        let T::Use(ExpressionItem::Variable(variable), binding) = item else {
+            continue;
+        };
+        if let UseBinding::All(keys) = binding {
+            let prefix = variable.get_name(namespaces.to_vec(), usespace.get_bindings())
+                .ok_or_else(|| at(format!("Nothing to use in '{variable}'"), &location))?;
+            let names: BTreeSet<&String> = members.names.iter()
+                .filter(|m| m.len() == prefix.len() + 1 && m.starts_with(&prefix))
+                .filter(|m| members.is_visible(m, namespaces))
+                .filter_map(|m| m.last())
+                .collect();
+            if names.is_empty() {
+                Err(at(format!("Nothing to use in '{}'", prefix.join(".")), &location))?;
+            }
+            *keys = names.into_iter().filter(|k| !explicit.contains(*k)).cloned().collect();
+        }
+        let path = binding.format(variable);
+        for (key, value) in variable.get_use_bindings(binding, namespaces, usespace.get_bindings()) {
+            if let Some(name) = value.get_resolved_name() {
+                members.check_visibility(name, namespaces).map_err(|e| at(e, &location))?;
+            }
+            if let Some((earlier, target)) = bound.get(&key) {
+                if *target != value {
+                    Err(at(format!("'use {earlier};' and 'use {path};' both bind '{key}'"), &location))?;
+                }
+            }
+            bound.insert(key.to_string(), (path.to_string(), value.clone()));
+            usespace.bind(key, value);
+        }
+    }
+    for item in ast.iter_mut() {
+        if let T::Namespace(name, items, attributes) = item {
+            let mut names = namespaces.to_vec();
+            names.push(name.to_string());
+            if attributes.is_module {
+                expand_tree(items, &names, &mut Usespace::default(), members)?;
+            } else {
+                expand_tree(items, &names, usespace, members)?;
+            }
+        }
+    }
+    usespace.leave(mark);
+    Ok(())
+}
+
+
+/// An error about the item at `location`.
+fn at(message: String, location: &Option<Location>) -> Diagnostic {
+    let diagnostic = Diagnostic::from(message);
+    match location {
+        Some(location) => diagnostic.with_location(location.clone()),
+        None => diagnostic,
+    }
+}
+
+
+fn resolve_tree(ast: &mut AbstractSyntaxTree, namespaces: &[String], usespace: &mut Usespace, members: &Members) -> Result<(), Diagnostic> {
+    type T = AbstractSyntaxItem;
+    let mark = usespace.enter(ast, namespaces);
+    ast.retain(|item| !matches!(item, T::Use(..) | T::Public(_)));
+    let mut location = None;
+    for item in ast.iter_mut() {
+        let resolve = |items: &mut [ExpressionItem], location: &Option<Location>| {
+            resolve_items(items, namespaces, usespace, members).map_err(|e| at(e, location))
+        };
+        match item {
+            T::Location(l) => location = Some(l.clone()),
+            T::Expression(items, _) => resolve(items, &location)?,
+            T::Graph(color, _, items, _) => {
+                resolve(color.as_mut_slice(), &location)?;
+                resolve(items, &location)?;
+            },
+            T::Const(variable, items) => {
+                resolve(std::slice::from_mut(variable), &location)?;
+                resolve(items, &location)?;
+            },
+            T::Export(variable) => resolve(std::slice::from_mut(variable), &location)?,
+            T::Namespace(name, items, attributes) => {
+                let mut names = namespaces.to_vec();
+                names.push(name.to_string());
+                if attributes.is_module {
+                    resolve_tree(items, &names, &mut Usespace::default(), members)?;
+                } else {
+                    resolve_tree(items, &names, usespace, members)?;
+                }
+            },
+            _ => (),
+        }
+    }
+    usespace.leave(mark);
+    Ok(())
+}
+
+
+fn resolve_items(items: &mut [ExpressionItem], namespaces: &[String], usespace: &Usespace, members: &Members) -> Result<(), String> {
+    for item in items {
+        if let ExpressionItem::Variable(variable) = item {
+            *variable = variable.resolve(namespaces, usespace.get_bindings())
+                .ok_or(format!("'{variable}' goes through a standard library name, which has no members"))?;
+            if let Some(name) = variable.get_resolved_name() {
+                members.check_visibility(name, namespaces)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use crate::ast::uses::{expand_uses, resolve_names};
+    use crate::ast::{AbstractSyntaxItem, AbstractSyntaxTree, ExpressionItem};
+    use crate::parse;
+    use crate::preprocessor::files::MemoryFileProvider;
+    use crate::preprocessor::Preprocessor;
+
+    /// Every expression of `source` once its names are resolved.
+    fn resolve(source: &str) -> Result<Vec<String>, String> {
+        let mut provider = MemoryFileProvider::new();
+        provider.insert("main.dmn", source);
+        let mut preprocessor = Preprocessor::new(&provider, HashMap::new());
+        let chars = preprocessor.preprocess("main.dmn").map_err(|e| e.to_string())?;
+        let mut ast = parse(&chars, &preprocessor.source_map).map_err(|e| e.to_string())?;
+        expand_uses(&mut ast).map_err(|e| e.to_string())?;
+        resolve_names(&mut ast).map_err(|e| e.to_string())?;
+        let mut result = vec![];
+        write_expressions(&ast, &mut result);
+        Ok(result)
+    }
+
+    fn write_expressions(ast: &AbstractSyntaxTree, result: &mut Vec<String>) {
+        for item in ast {
+            match item {
+                AbstractSyntaxItem::Expression(items, _) => result.push(items.iter().map(|item| match item {
+                    ExpressionItem::Variable(variable) => variable.to_string(),
+                    ExpressionItem::Other(token) => token.get_latex(),
+                }).collect()),
+                AbstractSyntaxItem::Namespace(_, items, _) => write_expressions(items, result),
+                _ => (),
+            }
+        }
+    }
+
+    #[test]
+    fn restores_shadowed_uses() {
+        let source = "namespace a { this.x = 1; }\nnamespace b { this.x = 2; }\nuse a.x;\n\
+            namespace c { use b.x; this.q = x; }\nthis.r = x;";
+        assert_eq!(resolve(source).unwrap(), ["a.x=1", "b.x=2", "c.q=b.x", "r=a.x"]);
+    }
+
+    #[test]
+    fn resolves_grouped_uses() {
+        let source = "namespace a { this.x = 1; namespace n { this.y = 2; this.z = 3; } }\n\
+            use a.{x, n.y as w, n.*};\nthis.p = x + w + z;";
+        assert_eq!(resolve(source).unwrap()[3], "p=a.x+a.n.y+a.n.z");
+    }
+
+    #[test]
+    fn prefers_explicit_uses_to_globs() {
+        let source = "namespace a { this.x = 1; }\nnamespace b { this.x = 2; }\nuse a.*;\nuse b.x;\nthis.p = x;";
+        assert_eq!(resolve(source).unwrap()[2], "p=b.x");
+    }
+
+    #[test]
+    fn reports_bad_uses() {
+        let conflict = "namespace a { this.x = 1; }\nnamespace b { this.x = 2; }\nuse a.x;\nuse b.x;\nthis.p = x;";
+        assert_eq!(resolve(conflict).unwrap_err(), "main.dmn:4: 'use a.x;' and 'use b.x;' both bind 'x'");
+        let conflict = "namespace a { this.x = 1; }\nnamespace b { this.x = 2; }\nuse a.x;\nnamespace c {\n    use a.x;\n    use b.x;\n}";
+        assert_eq!(resolve(conflict).unwrap_err(), "main.dmn:6: 'use a.x;' and 'use b.x;' both bind 'x'");
+        assert_eq!(resolve("namespace a { }\nuse a.*;").unwrap_err(), "main.dmn:2: Nothing to use in 'a'");
+        assert_eq!(resolve("this.p = 1;\nuse std.sin.*;").unwrap_err(), "main.dmn:2: Nothing to use in 'std.sin'");
+        let error = resolve("use std.sin;\nthis.p = sin.x;").unwrap_err();
+        assert_eq!(error, "main.dmn:2: 'sin.x' goes through a standard library name, which has no members");
+        assert_eq!(resolve("use std.sin;\nthis.p = sin(1);").unwrap(), ["p=std.sin\\left(1\\right)"]);
+    }
+
+    #[test]
+    fn hides_private_members() {
+        let geometry = "namespace g { pub this.area(r) = this.square(r); this.square(r) = r * r; }\n";
+        assert_eq!(resolve(&format!("{geometry}this.p = g.area(1);")).unwrap()[2], "p=g.area\\left(1\\right)");
+        let error = "main.dmn:2: 'g.square' is private to 'g' and cannot be used from the top level";
+        assert_eq!(resolve(&format!("{geometry}this.p = g.square(1);")).unwrap_err(), error);
+        assert_eq!(resolve(&format!("{geometry}use g.square;")).unwrap_err(), error);
+        let error = "main.dmn:3: 'g.square' is private to 'g' and cannot be used from 'h'";
+        assert_eq!(resolve(&format!("{geometry}namespace h {{\n    this.p = g.square(1);\n}}")).unwrap_err(), error);
+        assert_eq!(resolve("namespace g { this.a = 1; this.b = 2; }\nthis.p = g.b;").unwrap()[2], "p=g.b");
+    }
+
+    #[test]
+    fn hides_members_of_private_namespaces() {
+        let source = "namespace g { pub this.a = 1; namespace inner { pub this.x = 1; } }\nthis.p = g.inner.x;";
+        assert_eq!(resolve(source).unwrap_err(), "main.dmn:2: 'g.inner.x' cannot be used from the top level, 'g.inner' is private to 'g'");
+        let source = "namespace g { pub this.a = 1; namespace inner { pub this.x = 1; } this.q = this.inner.x; }";
+        assert_eq!(resolve(source).unwrap()[2], "g.q=g.inner.x");
+        let source = "namespace g { pub namespace s { this.x = 1; } this.b = 2; }\nthis.p = g.s.x;";
+        assert_eq!(resolve(source).unwrap()[2], "p=g.s.x");
+    }
+
+    #[test]
+    fn globs_skip_private_members() {
+        let source = "namespace g { pub this.a = 1; this.b = 2; }\nuse g.*;\nthis.p = a + b;";
+        assert_eq!(resolve(source).unwrap()[2], "p=g.a+b");
+        assert_eq!(resolve("namespace g { pub this.a = 1; this.b = 2; namespace h { use g.*; this.p = b; } }").unwrap()[2], "g.h.p=g.b");
+    }
+}
@@ -0,0 +1,230 @@
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Command {
+    Build,
+    Serve,
+    Check,
+    Fmt,
+    Import,
+    Dump,
+    Help,
+    Version,
+} impl Command {
+    pub fn from_string(string: &str) -> Option<Self> {
+        match string {
+            "build" => Some(Self::Build),
+            "serve" => Some(Self::Serve),
+            "check" => Some(Self::Check),
+            "fmt" => Some(Self::Fmt),
+            "import" => Some(Self::Import),
+            "dump" => Some(Self::Dump),
+            "help" | "-h" | "--help" => Some(Self::Help),
+            "version" | "-V" | "--version" => Some(Self::Version),
+            _ => None,
+        }
+    }
+    pub fn get_name(&self) -> &'static str {
+        match self {
+            Self::Build => "build",
+            Self::Serve => "serve",
+            Self::Check => "check",
+            Self::Fmt => "fmt",
+            Self::Import => "import",
+            Self::Dump => "dump",
+            Self::Help => "help",
+            Self::Version => "version",
+        }
+    }
+    pub fn get_summary(&self) -> &'static str {
+        match self {
+            Self::Build => "Compile a desmon file into an HTML page",
+            Self::Serve => "Preview a desmon file on localhost, reloading on change",
+            Self::Check => "Compile a desmon file without writing any output",
+            Self::Fmt => "Reformat a desmon file in place",
+            Self::Import => "Convert a Desmos state JSON file into desmon code",
+            Self::Dump => "Print an intermediate compiler stage",
+            Self::Help => "Print help for a command",
+            Self::Version => "Print the compiler version",
+        }
+    }
+    pub fn get_usage(&self) -> &'static str {
+        match self {
+            Self::Build => "desmon build [options] <file>",
+            Self::Serve => "desmon serve [options] <file>",
+            Self::Check => "desmon check [options] <file>",
+            Self::Fmt => "desmon fmt [options] <file>",
+            Self::Import => "desmon import [options] <file.json>",
+            Self::Dump => "desmon dump (--stage <stage> | --expand) [options] <file>",
+            Self::Help => "desmon help [command]",
+            Self::Version => "desmon version",
+        }
+    }
+    pub fn get_options(&self) -> Vec<Opt> {
+        let output = Opt::new("output", Some('o'), Some("path"), "Write to a file instead of stdout");
+        let template = Opt::new("template", None, Some("path"), "HTML page with {{script}}, {{state}} and {{options}} placeholders");
+        let option = Opt::new("option", None, Some("key=value"), "Calculator option passed to Desmos (repeatable)");
+        let include = Opt::new("include", Some('I'), Some("dir"), "Also look for included files in a directory (repeatable)");
+        let define = Opt::new("define", Some('D'), Some("name=value"), "Set a preprocessor definition, '1' if no value (repeatable)");
+        let lints = [
+            Opt::new("allow", None, Some("lint"), "Silence a lint, or 'all' of them (repeatable)"),
+            Opt::new("warn", None, Some("lint"), "Report a lint as a warning, the default (repeatable)"),
+            Opt::new("deny", None, Some("lint"), "Fail when a lint finds something (repeatable)"),
+        ];
+        let keep_unused = Opt::new("keep-unused", None, None, "Keep definitions that nothing on the graph uses");
+        let folders = Opt::new("folders", None, Some("strategy"), "Namespaces with a folder: every, top, none or a depth like 2 (default every)");
+        let manifest = Opt::new("manifest", None, Some("path"), "Also write the LaTeX and expression id of each name, as JSON or a .js module");
+        match self {
+            Self::Build => [
+                vec![output, include, define.clone(), keep_unused.clone(), folders.clone(), manifest.clone(), template, option],
+                lints.to_vec(),
+                vec![Opt::new("watch", Some('w'), None, "Rebuild whenever the file or its includes change")],
+            ].concat(),
+            Self::Serve => [
+                vec![include, define, keep_unused, folders.clone(), manifest, template, option],
+                lints.to_vec(),
+                vec![Opt::new("port", Some('p'), Some("port"), "Port to listen on (default 8080)")],
+            ].concat(),
+            Self::Check => [vec![include, define], lints.to_vec()].concat(),
+            Self::Help | Self::Version => vec![],
+            Self::Fmt => vec![
+                output,
+                Opt::new("check", None, None, "Only report whether the file is formatted"),
+            ],
+            Self::Import => vec![output],
+            Self::Dump => vec![
+                output,
+                include,
+                define,
+                keep_unused,
+                folders,
+                Opt::new("stage", None, Some("stage"), "One of preprocess, expand, tokens, ast or lines"),
+                Opt::new("expand", None, None, "List every definition expansion, same as '--stage expand'"),
+            ],
+        }
+    }
+    pub fn get_all() -> [Self; 8] {
+        [Self::Build, Self::Serve, Self::Check, Self::Fmt, Self::Import, Self::Dump, Self::Help, Self::Version]
+    }
+}
+
+
+#[derive(Debug, Clone)]
+pub struct Opt {
+    pub long: &'static str,
+    pub short: Option<char>,
+    pub value: Option<&'static str>,
+    pub help: &'static str,
+} impl Opt {
+    pub fn new(long: &'static str, short: Option<char>, value: Option<&'static str>, help: &'static str) -> Self {
+        Self { long, short, value, help }
+    }
+    pub fn matches(&self, argument: &str) -> bool {
+        if let Some(long) = argument.strip_prefix("--") {
+            long == self.long
+        } else if let Some(short) = argument.strip_prefix('-') {
+            self.short.is_some_and(|c| short.chars().eq([c]))
+        } else {
+            false
+        }
+    }
+}
+
+
+#[derive(Debug)]
+pub struct Arguments {
+    pub command: Command,
+    pub paths: Vec<String>,
+    values: Vec<(&'static str, String)>,
+} impl Arguments {
+    pub fn from_args(args: Vec<String>) -> Result<Self, String> {
+        let mut args = args.into_iter().skip(1);
+        let command = if let Some(name) = args.next() {
+            Command::from_string(&name).ok_or(format!("Unknown command '{name}', see 'desmon help'"))?
+        } else {
+            Command::Help
+        };
+        let options = command.get_options();
+        let mut paths = vec![];
+        let mut values = vec![];
+        let mut only_paths = false;
+        while let Some(argument) = args.next() {
+            if only_paths || argument == "-" || !argument.starts_with('-') {
+                paths.push(argument);
+            } else if argument == "--" {
+                only_paths = true;
+            } else {
+                let (name, inline) = match argument.split_once('=') {
+                    Some((name, value)) if name.starts_with("--") => (name.to_string(), Some(value.to_string())),
+                    _ => (argument.to_string(), None),
+                };
+                let option = options.iter().find(|o| o.matches(&name))
+                    .ok_or(format!("Unknown option '{name}' for 'desmon {}'", command.get_name()))?;
+                let value = if option.value.is_some() {
+                    inline.or_else(|| args.next())
+                        .ok_or(format!("Option '{name}' expects a value"))?
+                } else if inline.is_some() {
+                    Err(format!("Option '{name}' does not take a value"))?
+                } else {
+                    String::new()
+                };
+                values.push((option.long, value));
+            }
+        }
+        let max_paths = match command {
+            Command::Help => 1,
+            Command::Version => 0,
+            _ => {
+                if paths.is_empty() {
+                    Err(format!("Missing input file, usage: {}", command.get_usage()))?;
+                }
+                1
+            },
+        };
+        if paths.len() > max_paths {
+            Err(format!("Unexpected argument '{}'", paths[max_paths]))?;
+        }
+        Ok(Self { command, paths, values })
+    }
+    pub fn get_path(&self) -> Option<&String> {
+        self.paths.first()
+    }
+    pub fn get(&self, long: &str) -> Option<&String> {
+        self.values.iter().rev().find(|(name, _)| *name == long).map(|(_, value)| value)
+    }
+    pub fn get_all(&self, long: &str) -> Vec<&String> {
+        self.values.iter().filter(|(name, _)| *name == long).map(|(_, value)| value).collect()
+    }
+    pub fn has(&self, long: &str) -> bool {
+        self.values.iter().any(|(name, _)| *name == long)
+    }
+}
+
+
+pub fn get_help(command: Option<Command>) -> String {
+    let mut result = String::new();
+    if let Some(command) = command {
+        result.push_str(&format!("{}\n\nUsage: {}\n", command.get_summary(), command.get_usage()));
+        let options = command.get_options();
+        if !options.is_empty() {
+            result.push_str("\nOptions:\n");
+        }
+        for o in options {
+            let mut name = match o.short {
+                Some(c) => format!("-{c}, --{}", o.long),
+                None => format!("    --{}", o.long),
+            };
+            if let Some(value) = o.value {
+                name.push_str(&format!(" <{value}>"));
+            }
+            result.push_str(&format!("  {name:<28} {}\n", o.help));
+        }
+    } else {
+        result.push_str("----The-Desmon-Compiler----\n");
+        result.push_str("Compile Desmon code into Desmos graphing calculators.\n\n");
+        result.push_str("Usage: desmon <command> [options] <file>\n\nCommands:\n");
+        for c in Command::get_all() {
+            result.push_str(&format!("  {:<10} {}\n", c.get_name(), c.get_summary()));
+        }
+        result.push_str("\nRun 'desmon help <command>' for the options of a command.\n");
+    }
+    result
+}
@@ -0,0 +1,575 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::Path;
+
+use crate::ast::dead_code::get_definition;
+use crate::ast::{AbstractSyntaxItem, AbstractSyntaxTree, AbstractSyntaxTreeTrait, ExpressionAttributes, ExpressionItem, NamespaceAttributes, Variable};
+use crate::json::Json;
+
+
+/// How namespaces become Desmos folders, which cannot be nested.
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub enum FolderStrategy {
+    /// A folder for every namespace.
+    #[default]
+    Every,
+    /// Folders for the namespaces up to a depth, holding everything deeper.
+    Depth(usize),
+    /// No folders at all.
+    None,
+} impl FolderStrategy {
+    pub fn from_string(string: &str) -> Option<Self> {
+        match string {
+            "every" => Some(Self::Every),
+            "top" => Some(Self::Depth(1)),
+            "none" => Some(Self::None),
+            _ => string.parse().ok().filter(|&depth| depth > 0).map(Self::Depth),
+        }
+    }
+    fn has_folder(&self, depth: usize) -> bool {
+        match self {
+            Self::Every => true,
+            Self::Depth(max) => depth <= *max,
+            Self::None => false,
+        }
+    }
+}
+
+
+#[derive(Debug)]
+pub struct DesmosExpression {
+    pub latex: String,
+    pub folder_id: Option<String>,
+    pub opacity: Option<String>,
+    pub color_latex: Option<String>,
+    pub hidden: bool,
+    pub secret: bool,
+    pub readonly: bool,
+    pub id: Option<String>,
+    pub description: Option<String>,
+} impl DesmosExpression {
+    /// An expression in `folder_id` with its attributes and those of the
+    /// namespaces around it. A hidden namespace hides even `visible` expressions.
+    fn new(latex: String, folder_id: Option<String>, attributes: ExpressionAttributes, outer: &NamespaceAttributes) -> Self {
+        Self {
+            latex,
+            folder_id,
+            opacity: None,
+            color_latex: None,
+            hidden: outer.hidden || attributes.hidden.unwrap_or(true),
+            secret: outer.secret || attributes.secret,
+            readonly: attributes.readonly,
+            id: attributes.id,
+            description: attributes.description,
+        }
+    }
+}
+
+
+/// Gives every definition without an `id` its full name as its id, so scripts
+/// on the page can find it. Desmos does not load two items with one id, so
+/// an `id` given twice or given to a folder is an error, and a definition
+/// whose name is already an id gets a numbered one, like `n.a-2`. Names must
+/// have been resolved.
+pub fn assign_ids(ast: &mut AbstractSyntaxTree, strategy: FolderStrategy) -> Result<(), String> {
+    let mut taken = HashSet::new();
+    collect_ids(ast, &[], strategy, &mut taken)?;
+    fill_ids(ast, &mut taken);
+    Ok(())
+}
+
+
+/// The id of the folder of the namespace `names`. Definitions are named after
+/// their full name, so folders get ids of their own.
+fn get_folder_id(names: &[String]) -> String {
+    format!("folder:{}", names.join("."))
+}
+
+
+fn collect_ids(ast: &AbstractSyntaxTree, namespaces: &[String], strategy: FolderStrategy, taken: &mut HashSet<String>) -> Result<(), String> {
+    type T = AbstractSyntaxItem;
+    for item in ast {
+        let id = match item {
+            T::Expression(_, attributes) | T::Graph(_, _, _, attributes) => attributes.id.clone(),
+            T::Namespace(name, items, _) => {
+                let mut names = namespaces.to_vec();
+                names.push(name.to_string());
+                collect_ids(items, &names, strategy, taken)?;
+                strategy.has_folder(names.len()).then(|| get_folder_id(&names))
+            },
+            _ => None,
+        };
+        if let Some(id) = id {
+            if !taken.insert(id.to_string()) {
+                Err(format!("More than one item in the calculator has the id '{id}'"))?;
+            }
+        }
+    }
+    Ok(())
+}
+
+
+fn fill_ids(ast: &mut AbstractSyntaxTree, taken: &mut HashSet<String>) {
+    type T = AbstractSyntaxItem;
+    for item in ast {
+        match item {
+            T::Expression(items, attributes) if attributes.id.is_none() => {
+                let Some(name) = get_definition(items).and_then(Variable::get_resolved_name) else {
+                    continue;
+                };
+                let name = name.join(".");
+                let id = (1..)
+                    .map(|n| if n == 1 { name.to_string() } else { format!("{name}-{n}") })
+                    .find(|id| !taken.contains(id))
+                    .unwrap_or(name);
+                taken.insert(id.to_string());
+                attributes.id = Some(id);
+            },
+            T::Namespace(_, items, _) => fill_ids(items, taken),
+            _ => (),
+        }
+    }
+}
+
+
+/// Where a desmon definition ends up in the calculator, for scripts on the page.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DesmosName {
+    /// The full desmon name, like `ui.buttons.count`.
+    pub name: String,
+    pub latex: String,
+    /// The id of the expression defining the name, if it has one.
+    pub id: Option<String>,
+} impl DesmosName {
+    /// Every definition in the calculator, sorted by name. Constants are not
+    /// in the calculator, their values are written where they are used.
+    /// Names must have been resolved, and ids assigned.
+    pub fn vec_from_ast(ast: &AbstractSyntaxTree) -> Vec<Self> {
+        let ids = ast.get_variable_ids();
+        let mut definitions = BTreeMap::new();
+        Self::collect_definitions(ast, &mut definitions);
+        definitions.into_iter().map(|(name, id)| Self {
+            name: name.join("."),
+            latex: Variable::Absolute(name).get_latex(&ids),
+            id,
+        }).collect()
+    }
+    fn collect_definitions(ast: &AbstractSyntaxTree, result: &mut BTreeMap<Vec<String>, Option<String>>) {
+        type T = AbstractSyntaxItem;
+        for item in ast {
+            match item {
+                T::Expression(items, attributes) => {
+                    if let Some(name) = get_definition(items).and_then(Variable::get_resolved_name) {
+                        result.entry(name.to_vec()).or_insert(attributes.id.clone());
+                    }
+                },
+                T::Namespace(_, items, _) => Self::collect_definitions(items, result),
+                _ => (),
+            }
+        }
+    }
+    /// A JSON object from each name to its LaTeX and expression id.
+    pub fn get_manifest(vec: &[Self]) -> Json {
+        let mut result = Json::object();
+        for name in vec {
+            let mut entry = Json::object();
+            entry.insert("latex", Json::string(&name.latex));
+            if let Some(id) = &name.id {
+                entry.insert("id", Json::string(id));
+            }
+            result.insert(&name.name, entry);
+        }
+        result
+    }
+    /// An ES module with the manifest as `names`, and `helper` to follow the
+    /// value of a name with a `HelperExpression`.
+    pub fn get_module(vec: &[Self]) -> String {
+        NAMES_MODULE.replace("{{names}}", &Self::get_manifest(vec).to_string())
+    }
+    /// The manifest to write to `path`, a module for `.js` and `.mjs` paths
+    /// so scripts can import it instead of fetching and parsing it.
+    pub fn get_manifest_file(vec: &[Self], path: &Path) -> String {
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("js" | "mjs") => Self::get_module(vec),
+            _ => format!("{}\n", Self::get_manifest(vec)),
+        }
+    }
+}
+
+
+#[derive(Debug)]
+pub struct DesmosFolder {
+    pub id: String,
+    pub title: String,
+    pub collapsed: bool,
+    pub hidden: bool,
+    pub secret: bool,
+}
+
+
+#[derive(Debug)]
+pub enum DesmosLine {
+    Expression(DesmosExpression),
+    Folder(DesmosFolder),
+    Text(String, Option<String>),
+} impl DesmosLine {
+    pub fn vec_from_ast(ast: AbstractSyntaxTree, strategy: FolderStrategy) -> Vec<Self> {
+        let mut result = vec![];
+        let ids = &ast.get_variable_ids();
+        Self::fill_from_ast(&mut result, ast, vec![], None, NamespaceAttributes::default(), strategy, ids);
+        result
+    }
+    /// Fills in the lines for a tree whose names have been resolved. Namespaces
+    /// without a folder of their own go in `folder_id`, after the lines around
+    /// them. `outer` holds what the tree inherits from the namespaces around it.
+    pub fn fill_from_ast(
+        vec: &mut Vec<Self>,
+        ast: AbstractSyntaxTree,
+        namespaces: Vec<String>,
+        folder_id: Option<String>,
+        outer: NamespaceAttributes,
+        strategy: FolderStrategy,
+        ids: &HashMap<Vec<String>, usize>,
+    ) {
+        type T = AbstractSyntaxItem;
+        let mut folders = vec![];
+        for i in ast {
+            match i {
+                T::Expression(e, attributes) => {
+                    let latex = ExpressionItem::vec_to_latex(e, ids);
+                    vec.push(Self::Expression(DesmosExpression::new(latex, folder_id.clone(), attributes, &outer)));
+                },
+                T::Graph(c, opacity, e, attributes) => {
+                    let color = if let Some(c) = c { 
+                        c.get_latex(ids)
+                    } else { 
+                        String::new() 
+                    };
+                    // Graphs are drawn unless they say otherwise.
+                    let attributes = ExpressionAttributes { hidden: Some(attributes.hidden.unwrap_or(false)), ..attributes };
+                    let latex = ExpressionItem::vec_to_latex(e, ids);
+                    vec.push(Self::Expression(DesmosExpression {
+                        opacity,
+                        color_latex: Some(color),
+                        ..DesmosExpression::new(latex, folder_id.clone(), attributes, &outer)
+                    }));
+                },
+                T::Namespace(name, e, attributes) => {
+                    let mut names = namespaces.to_vec();
+                    names.push(name.to_string());
+                    // Desmos folders do not nest, so a hidden or secret namespace
+                    // passes that on to its children itself.
+                    let inner = NamespaceAttributes {
+                        hidden: outer.hidden || attributes.hidden,
+                        secret: outer.secret || attributes.secret,
+                        ..NamespaceAttributes::default()
+                    };
+                    if strategy.has_folder(names.len()) {
+                        let path = names.join(".");
+                        let id = get_folder_id(&names);
+                        folders.push(Self::Folder(DesmosFolder {
+                            id: id.to_string(),
+                            title: attributes.title.unwrap_or(path),
+                            collapsed: attributes.collapsed.unwrap_or(true),
+                            hidden: inner.hidden,
+                            secret: inner.secret,
+                        }));
+                        Self::fill_from_ast(&mut folders, e, names, Some(id), inner, strategy, ids);
+                    } else {
+                        Self::fill_from_ast(&mut folders, e, names, folder_id.clone(), inner, strategy, ids);
+                    }
+                },
+                T::Text(t) => vec.push(Self::Text(t, folder_id.clone())),
+                _ => (),
+            }
+        }
+        vec.append(&mut folders);
+    }
+    pub fn get_desmos_object_js(&self) -> Json {
+        let mut result = Json::object();
+        match self {
+            Self::Expression(e) => {
+                result.insert("type", Json::string("expression"));
+                result.insert("latex", Json::string(&e.latex));
+                if let Some(i) = &e.folder_id {
+                    result.insert("folderId", Json::string(i));
+                }
+                if let Some(c) = &e.color_latex {
+                    result.insert("colorLatex", Json::string(c));
+                }
+                if e.hidden {
+                    result.insert("hidden", Json::Bool(true));
+                }
+                if let Some(o) = &e.opacity {
+                    result.insert("fillOpacity", Json::Number(o.to_string()));
+                }
+                if e.secret {
+                    result.insert("secret", Json::Bool(true));
+                }
+                if e.readonly {
+                    result.insert("readonly", Json::Bool(true));
+                }
+                if let Some(i) = &e.id {
+                    result.insert("id", Json::string(i));
+                }
+                if let Some(d) = &e.description {
+                    result.insert("description", Json::string(d));
+                }
+            },
+            Self::Folder(f) => {
+                result.insert("type", Json::string("folder"));
+                result.insert("title", Json::string(&f.title));
+                result.insert("id", Json::string(&f.id));
+                result.insert("collapsed", Json::Bool(f.collapsed));
+                if f.hidden {
+                    result.insert("hidden", Json::Bool(true));
+                }
+                if f.secret {
+                    result.insert("secret", Json::Bool(true));
+                }
+            },
+            Self::Text(text, folder_id) => {
+                result.insert("type", Json::string("text"));
+                result.insert("text", Json::string(text));
+                if let Some(i) = folder_id {
+                    result.insert("folderId", Json::string(i));
+                }
+            },
+        }
+        result
+    }
+}
+
+
+pub const DEFAULT_TEMPLATE: &str = r"<!DOCTYPE html>
+<html style='height: 100%;'>
+<body style='height: 100%; margin: 0%'>
+{{script}}
+<div id='calculator' style='width: 100%; height: 100%;'></div>
+<script>
+    var elt = document.getElementById('calculator');
+    var calculator = Desmos.GraphingCalculator(elt, {{options}});
+    calculator.setState({{state}});
+</script>
+</body>
+</html>
+";
+
+
+const NAMES_MODULE: &str = r"export const names = {{names}};
+
+export function helper(calculator, name) {
+    if (!(name in names)) {
+        throw new Error(`No desmon name '${name}'`);
+    }
+    return calculator.HelperExpression({ latex: names[name].latex });
+}
+";
+
+
+#[derive(Debug)]
+pub struct GraphingCalculator {
+    pub expressions: Vec<DesmosLine>, 
+    pub api_key: String,
+    pub template: String,
+    pub options: Json,
+} impl GraphingCalculator {
+    pub fn from(expressions: Vec<DesmosLine>) -> Self {
+        Self {
+            expressions,
+            api_key: "dcb31709b452b1cf9dc26972add0fda6".to_string(),
+            template: DEFAULT_TEMPLATE.to_string(),
+            options: Json::object(),
+        }
+    }
+    pub fn get_html(&self) -> String {
+        let script = format!("<script src='{}'></script>", self.get_api_link());
+        self.template
+            .replace("{{script}}", &script)
+            .replace("{{options}}", &self.options.to_string())
+            .replace("{{state}}", &self.get_state().to_string())
+    }
+    pub fn get_state(&self) -> Json {
+        let mut graph = Json::object();
+        for key in ["showGrid", "showXAxis", "showYAxis", "xAxisNumbers", "yAxisNumbers"] {
+            graph.insert(key, Json::Bool(false));
+        }
+        let mut expressions = Json::object();
+        expressions.insert("list", Json::Array(self.expressions.iter().map(DesmosLine::get_desmos_object_js).collect()));
+        let mut result = Json::object();
+        result.insert("version", Json::Number("9".to_string()));
+        result.insert("expressions", expressions);
+        result.insert("graph", graph);
+        result
+    }
+    fn get_api_link(&self) -> String {
+        let url_start = "https://www.desmos.com/api/v1.7/calculator.js?apiKey=";
+        format!("{url_start}{}", self.api_key)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use crate::desmos::{DesmosLine, DesmosName, FolderStrategy};
+    use crate::{compile, Options};
+
+    /// Each line of the calculator, with the folder it is in and whether it is
+    /// hidden or secret. Expressions are written as what follows their '='.
+    fn get_lines(source: &str, folders: FolderStrategy) -> Vec<String> {
+        let options = Options { folders, eliminate_dead_code: false, ..Options::default() };
+        let output = compile(source, &options).unwrap_or_else(|e| panic!("{e}"));
+        output.calculator.expressions.iter().map(|line| match line {
+            DesmosLine::Expression(e) => format!(
+                "{} in {}{}{}",
+                e.latex.split_once('=').map_or(e.latex.as_str(), |(_, value)| value),
+                e.folder_id.as_deref().unwrap_or("-"),
+                if e.hidden { ", hidden" } else { "" },
+                if e.secret { ", secret" } else { "" },
+            ),
+            DesmosLine::Folder(f) => format!(
+                "folder {} '{}'{}{}{}",
+                f.id,
+                f.title,
+                if f.collapsed { ", collapsed" } else { "" },
+                if f.hidden { ", hidden" } else { "" },
+                if f.secret { ", secret" } else { "" },
+            ),
+            DesmosLine::Text(text, folder_id) => format!("text {text} in {}", folder_id.as_deref().unwrap_or("-")),
+        }).collect()
+    }
+
+    #[test]
+    fn picks_folders_by_depth() {
+        let nested = "graph: @y = 1;\nnamespace a { graph: @y = 2; namespace b { graph: @y = 3; namespace c { graph: @y = 4; } } }";
+        assert_eq!(get_lines(nested, FolderStrategy::Every), [
+            "1 in -",
+            "folder folder:a \'a', collapsed",
+            "2 in folder:a",
+            "folder folder:a.b \'a.b', collapsed",
+            "3 in folder:a.b",
+            "folder folder:a.b.c \'a.b.c', collapsed",
+            "4 in folder:a.b.c",
+        ]);
+        assert_eq!(get_lines(nested, FolderStrategy::Depth(2)), [
+            "1 in -",
+            "folder folder:a \'a', collapsed",
+            "2 in folder:a",
+            "folder folder:a.b \'a.b', collapsed",
+            "3 in folder:a.b",
+            "4 in folder:a.b",
+        ]);
+        assert_eq!(get_lines(nested, FolderStrategy::Depth(1)), ["1 in -", "folder folder:a \'a', collapsed", "2 in folder:a", "3 in folder:a", "4 in folder:a"]);
+        assert_eq!(get_lines(nested, FolderStrategy::None), ["1 in -", "2 in -", "3 in -", "4 in -"]);
+    }
+
+    #[test]
+    fn reads_folder_strategies() {
+        assert_eq!(FolderStrategy::from_string("every"), Some(FolderStrategy::Every));
+        assert_eq!(FolderStrategy::from_string("top"), Some(FolderStrategy::Depth(1)));
+        assert_eq!(FolderStrategy::from_string("none"), Some(FolderStrategy::None));
+        assert_eq!(FolderStrategy::from_string("2"), Some(FolderStrategy::Depth(2)));
+        assert_eq!(FolderStrategy::from_string("0"), None);
+        assert_eq!(FolderStrategy::from_string("all"), None);
+    }
+
+    #[test]
+    fn hides_nested_namespaces() {
+        let source = "@[hidden]\nnamespace a { graph: @y = 2; namespace b { graph: @y = 3; } graph: @y = @x; }\nnamespace c { graph: @y = 1; }";
+        assert_eq!(get_lines(source, FolderStrategy::Every), [
+            "folder folder:a \'a', collapsed, hidden",
+            "2 in folder:a, hidden",
+            "x in folder:a, hidden",
+            "folder folder:a.b \'a.b', collapsed, hidden",
+            "3 in folder:a.b, hidden",
+            "folder folder:c \'c', collapsed",
+            "1 in folder:c",
+        ]);
+        let source = "@[hidden]\nnamespace a { namespace b { graph: @y = @x; } }";
+        assert_eq!(get_lines(source, FolderStrategy::None), ["x in -, hidden"]);
+    }
+
+    #[test]
+    fn titles_and_opens_folders() {
+        let source = "@[title = \"Control panel\", collapsed = false]\nnamespace a { graph: @y = 2; }\nnamespace b { graph: @y = 3; }";
+        assert_eq!(get_lines(source, FolderStrategy::Every), ["folder folder:a \'Control panel'", "2 in folder:a", "folder folder:b \'b', collapsed", "3 in folder:b"]);
+    }
+
+    #[test]
+    fn passes_secret_to_nested_namespaces() {
+        let source = "@[secret]\nnamespace a { graph: @y = 2; @[hidden]\nnamespace b { graph: @y = 3; } }\ngraph: @y = 1;";
+        assert_eq!(get_lines(source, FolderStrategy::Every), [
+            "1 in -",
+            "folder folder:a \'a', collapsed, secret",
+            "2 in folder:a, secret",
+            "folder folder:a.b \'a.b', collapsed, hidden, secret",
+            "3 in folder:a.b, hidden, secret",
+        ]);
+    }
+
+    /// The Desmos state of each line of `source`, as JSON.
+    fn get_objects(source: &str) -> Vec<String> {
+        let options = Options { eliminate_dead_code: false, ..Options::default() };
+        let output = compile(source, &options).unwrap_or_else(|e| panic!("{e}"));
+        output.calculator.expressions.iter().map(|line| line.get_desmos_object_js().to_string()).collect()
+    }
+
+    #[test]
+    fn writes_expression_attributes() {
+        let source = "@[visible, readonly, id = \"speed\", description = \"Speed of the car\"]\nthis.speed = 2;";
+        assert_eq!(
+            get_objects(source),
+            [r#"{"type":"expression","latex":"a=2","readonly":true,"id":"speed","description":"Speed of the car"}"#],
+        );
+        assert_eq!(
+            get_objects("@[hidden, secret]\ngraph: @y = @x;"),
+            [r#"{"type":"expression","latex":"y=x","colorLatex":"","hidden":true,"secret":true}"#],
+        );
+    }
+
+    #[test]
+    fn hides_expressions_that_are_not_graphs() {
+        assert_eq!(get_objects("this.a = 1;"), [r#"{"type":"expression","latex":"a=1","hidden":true,"id":"a"}"#]);
+        assert_eq!(get_objects("graph: @y = @x;"), [r#"{"type":"expression","latex":"y=x","colorLatex":""}"#]);
+        assert_eq!(get_objects("@[hidden]\nnamespace n { @[visible]\nthis.a = 1 + 1; }"), [
+            r#"{"type":"folder","title":"n","id":"folder:n","collapsed":true,"hidden":true}"#,
+            r#"{"type":"expression","latex":"a=2","folderId":"folder:n","hidden":true,"id":"n.a"}"#,
+        ]);
+    }
+
+    #[test]
+    fn numbers_ids_taken_by_other_expressions() {
+        let ids = |source| {
+            let options = Options { eliminate_dead_code: false, ..Options::default() };
+            let names = compile(source, &options).unwrap_or_else(|e| panic!("{e}")).names;
+            names.into_iter().map(|n| format!("{} {}", n.name, n.id.unwrap_or_default())).collect::<Vec<_>>()
+        };
+        assert_eq!(ids("@[id = \"n.a\"]\nthis.b = 1;\nnamespace n { this.a = 2; }"), ["b n.a", "n.a n.a-2"]);
+        assert_eq!(ids("@[id = \"a-2\"]\nthis.b = 1;\n@[id = \"a\"]\nthis.c = 1;\nthis.a = 1;"), ["a a-3", "b a-2", "c a"]);
+    }
+
+    #[test]
+    fn rejects_ids_given_twice_or_to_folders() {
+        let error = |source| compile(source, &Options::default()).unwrap_err().to_string();
+        let source = "@[id = \"x\"]\ngraph: @y = 1;\n@[id = \"x\"]\ngraph: @y = 2;";
+        assert_eq!(error(source), "More than one item in the calculator has the id 'x'");
+        let source = "@[id = \"folder:n\"]\ngraph: @y = 1;\nnamespace n { graph: @y = 2; }";
+        assert_eq!(error(source), "More than one item in the calculator has the id 'folder:n'");
+        let options = Options { folders: FolderStrategy::None, ..Options::default() };
+        assert!(compile(source, &options).is_ok());
+    }
+
+    #[test]
+    fn writes_manifests_by_extension() {
+        let source = "@[id = \"speed\"]\nthis.speed = 2;";
+        let names = compile(source, &Options::default()).unwrap().names;
+        let json = DesmosName::get_manifest_file(&names, Path::new("names.json"));
+        assert_eq!(json, "{\"speed\":{\"latex\":\"a\",\"id\":\"speed\"}}\n");
+        for path in ["names.js", "out/names.mjs"] {
+            let module = DesmosName::get_manifest_file(&names, Path::new(path));
+            assert!(module.starts_with("export const names = {\"speed\":{\"latex\":\"a\",\"id\":\"speed\"}};\n"), "{module}");
+            assert!(module.contains("export function helper(calculator, name) {"), "{module}");
+        }
+    }
+}
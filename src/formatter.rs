@@ -0,0 +1,97 @@
+const INDENT: &str = "    ";
+
+
+/// Reindents desmon source by its curly brackets, trims trailing whitespace
+/// and collapses runs of blank lines. Comments, text and the bodies of
+/// multiline definitions are only reindented, never rewritten.
+pub fn format(source: &str) -> String {
+    let mut result = String::new();
+    let mut depth: usize = 0;
+    let mut is_comment = false;
+    let mut is_continuation = false;
+    let mut blank_lines = 0;
+    for line in source.lines() {
+        let trimmed = line.trim();
+        if is_continuation {
+            result.push_str(line.trim_end());
+            result.push('\n');
+            is_continuation = line.ends_with('\\');
+            continue;
+        }
+        if trimmed.is_empty() && !is_comment {
+            blank_lines += 1;
+            continue;
+        }
+        if blank_lines > 0 && !result.is_empty() {
+            result.push('\n');
+        }
+        blank_lines = 0;
+        let (opens, closes, leading_closes) = if is_comment {
+            (0, 0, 0)
+        } else {
+            count_brackets(trimmed)
+        };
+        if is_comment {
+            result.push_str(line.trim_end());
+        } else {
+            result.push_str(&INDENT.repeat(depth.saturating_sub(leading_closes)));
+            result.push_str(trimmed);
+        }
+        result.push('\n');
+        depth = (depth + opens).saturating_sub(closes);
+        is_comment = ends_in_comment(trimmed, is_comment);
+        is_continuation = !is_comment && trimmed.starts_with("/=") && trimmed.ends_with('\\');
+    }
+    result
+}
+
+
+fn count_brackets(line: &str) -> (usize, usize, usize) {
+    if line.starts_with('#') || line.starts_with("/=") || line.starts_with("/#") {
+        return (0, 0, 0);
+    }
+    let mut opens = 0;
+    let mut closes = 0;
+    let mut leading_closes = 0;
+    let mut is_leading = true;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '/' if matches!(chars.peek(), Some('/') | Some('*')) => break,
+            '{' => opens += 1,
+            '}' => {
+                if is_leading {
+                    leading_closes += 1;
+                }
+                closes += 1;
+            },
+            c if c.is_whitespace() => continue,
+            _ => (),
+        }
+        is_leading = is_leading && c == '}';
+    }
+    (opens, closes, leading_closes)
+}
+
+
+fn ends_in_comment(line: &str, mut is_comment: bool) -> bool {
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        if is_comment {
+            if c == '*' && chars.peek() == Some(&'/') {
+                chars.next();
+                is_comment = false;
+            }
+        } else if c == '/' {
+            match chars.peek() {
+                Some('/') => return false,
+                Some('*') => {
+                    chars.next();
+                    is_comment = true;
+                },
+                _ => (),
+            }
+        }
+    }
+    is_comment
+}
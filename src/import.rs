@@ -0,0 +1,393 @@
+use std::iter::Peekable;
+
+use crate::json::Json;
+
+
+const STD_CONSTS: [&str; 2] = ["pi", "tau"];
+const OPERATORS: [&str; 11] = ["=", "+", "-", "*", "/", "<", ">", "<=", ">=", "->", ":"];
+const STD_FUNCS: [&str; 9] = ["floor", "random", "abs", "sin", "cos", "tan", "rgb", "hsv", "length"];
+
+
+/// Converts a Desmos calculator state (as returned by `calculator.getState()`)
+/// into desmon code. Expressions using LaTeX that desmon can not express are
+/// kept as comments so nothing is silently lost. Definitions in a folder
+/// belong to its namespace and are brought into scope with a `use`, since
+/// Desmos lets every expression refer to them by their bare name.
+pub fn import_state(state: &Json) -> Result<String, &'static str> {
+    let list = match state.get("expressions").and_then(|e| e.get("list")) {
+        Some(Json::Array(list)) => list,
+        _ => Err("Desmos state has no expression list")?,
+    };
+    let mut colors: Vec<String> = vec![];
+    let mut body = String::new();
+    let mut folder: Option<&str> = None;
+    let mut namespace = String::new();
+    let mut uses = vec![];
+    for item in list {
+        let folder_id = item.get("folderId").and_then(Json::as_str);
+        if folder.is_some() && folder_id != folder {
+            body.push_str("}\n");
+            folder = None;
+        }
+        let indent = if folder.is_some() { "    " } else { "" };
+        match item.get("type").and_then(Json::as_str) {
+            Some("folder") => {
+                let title = item.get("title").and_then(Json::as_str).unwrap_or("folder");
+                let name = identifier_from_title(title);
+                let attributes = get_folder_attributes(item, title, &name);
+                if !attributes.is_empty() {
+                    body.push_str(&format!("@[{}]\n", attributes.join(", ")));
+                }
+                body.push_str(&format!("namespace {name} {{\n"));
+                folder = item.get("id").and_then(Json::as_str);
+                namespace = name;
+            },
+            Some("text") => {
+                for line in item.get("text").and_then(Json::as_str).unwrap_or("").lines() {
+                    body.push_str(&format!("{indent}# {line}\n"));
+                }
+            },
+            Some("expression") | None => {
+                let latex = item.get("latex").and_then(Json::as_str).unwrap_or("");
+                if latex.trim().is_empty() {
+                    continue;
+                }
+                let code = match code_from_latex(latex) {
+                    Ok(code) => match get_defined_name(&code) {
+                        Some(name) => {
+                            if folder.is_some() {
+                                uses.push(format!("use {namespace}.{name};\n"));
+                            }
+                            format!("this.{code}")
+                        },
+                        None => code,
+                    },
+                    Err(e) => {
+                        body.push_str(&format!("{indent}// {e}: {latex}\n"));
+                        continue;
+                    },
+                };
+                let attributes = get_expression_attributes(item);
+                if !attributes.is_empty() {
+                    body.push_str(&format!("{indent}@[{}]\n", attributes.join(", ")));
+                }
+                if item.get("hidden").and_then(Json::as_bool).unwrap_or(false) {
+                    body.push_str(&format!("{indent}{code};\n"));
+                    continue;
+                }
+                let mut graph = String::from("graph");
+                if let Some(rgb) = item.get("color").and_then(Json::as_str).and_then(rgb_from_hex) {
+                    let index = colors.iter().position(|c| *c == rgb).unwrap_or_else(|| {
+                        colors.push(rgb);
+                        colors.len() - 1
+                    });
+                    graph.push_str(&format!(" colors.c{index}"));
+                }
+                if let Some(opacity) = item.get("fillOpacity").and_then(Json::as_str) {
+                    if opacity.parse::<f64>().is_ok() {
+                        graph.push_str(&format!(" + {opacity}"));
+                    }
+                }
+                body.push_str(&format!("{indent}{graph}: {code};\n"));
+            },
+            Some(_) => body.push_str(&format!("{indent}// Unsupported Desmos item skipped\n")),
+        }
+    }
+    if folder.is_some() {
+        body.push_str("}\n");
+    }
+    let mut result = String::new();
+    if !colors.is_empty() {
+        result.push_str("namespace colors {\n");
+        for (index, rgb) in colors.iter().enumerate() {
+            result.push_str(&format!("    this.c{index} = std.rgb({rgb});\n"));
+        }
+        result.push_str("}\n");
+    }
+    for line in uses {
+        result.push_str(&line);
+    }
+    result.push_str(&body);
+    Ok(result)
+}
+
+
+/// The name code like `a = 1` or `f(x) = x` defines.
+fn get_defined_name(code: &str) -> Option<&str> {
+    let end = code.find(|c: char| !(c.is_alphanumeric() || c == '_')).unwrap_or(code.len());
+    let (name, mut rest) = code.split_at(end);
+    if !name.starts_with(|c: char| c.is_alphabetic()) {
+        return None;
+    }
+    if rest.starts_with('(') {
+        let mut depth = 0;
+        let close = rest.find(|c| {
+            match c {
+                '(' => depth += 1,
+                ')' => depth -= 1,
+                _ => (),
+            }
+            depth == 0
+        })?;
+        rest = &rest[close + 1..];
+    }
+    let rest = rest.trim_start().strip_prefix('=')?;
+    (!rest.starts_with('=')).then_some(name)
+}
+
+
+fn identifier_from_title(title: &str) -> String {
+    let mut result: String = title
+        .chars()
+        .map(|c| if c.is_alphanumeric() { c.to_ascii_lowercase() } else { '_' })
+        .collect();
+    if !result.starts_with(|c: char| c.is_alphabetic() || c == '_') {
+        result.insert(0, '_');
+    }
+    result
+}
+
+
+/// The namespace attributes that keep a folder looking the way it did.
+fn get_folder_attributes(item: &Json, title: &str, name: &str) -> Vec<String> {
+    let mut result = vec![];
+    if title != name {
+        result.push(format!("title = {}", string_literal(title)));
+    }
+    if !item.get("collapsed").and_then(Json::as_bool).unwrap_or(false) {
+        result.push("collapsed = false".to_string());
+    }
+    for flag in ["hidden", "secret"] {
+        if item.get(flag).and_then(Json::as_bool).unwrap_or(false) {
+            result.push(flag.to_string());
+        }
+    }
+    result
+}
+
+
+/// The expression attributes desmon keeps, except the id Desmos gives every expression.
+fn get_expression_attributes(item: &Json) -> Vec<String> {
+    let mut result = vec![];
+    for flag in ["secret", "readonly"] {
+        if item.get(flag).and_then(Json::as_bool).unwrap_or(false) {
+            result.push(flag.to_string());
+        }
+    }
+    if let Some(description) = item.get("description").and_then(Json::as_str) {
+        result.push(format!("description = {}", string_literal(description)));
+    }
+    result
+}
+
+
+fn string_literal(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+
+fn rgb_from_hex(hex: &str) -> Option<String> {
+    let hex = hex.strip_prefix('#')?;
+    if hex.len() != 6 {
+        return None;
+    }
+    let channel = |i: usize| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok();
+    Some(format!("{}, {}, {}", channel(0)?, channel(2)?, channel(4)?))
+}
+
+
+pub fn code_from_latex(latex: &str) -> Result<String, &'static str> {
+    let mut result = vec![];
+    fill_from_latex(&mut result, &mut latex.chars().peekable())?;
+    let is_word = |t: &str| t.ends_with(|c: char| c.is_alphanumeric() || c == '_');
+    let mut code = String::new();
+    let mut previous = "(";
+    let mut is_binary = false;
+    for token in &result {
+        let is_operator = OPERATORS.contains(&token.as_str());
+        let is_unary = is_operator && (OPERATORS.contains(&previous) || ["(", "[", "{", ","].contains(&previous));
+        let is_spaced = if is_unary {
+            is_binary
+        } else {
+            is_operator || is_binary || previous == "," || (is_word(previous) && is_word(token))
+        };
+        if is_spaced {
+            code.push(' ');
+        }
+        code.push_str(token);
+        is_binary = is_operator && !is_unary;
+        previous = token;
+    }
+    Ok(code.trim_start().to_string())
+}
+
+
+fn fill_from_latex<I>(result: &mut Vec<String>, chars: &mut Peekable<I>) -> Result<(), &'static str>
+where I: Iterator<Item = char>
+{
+    while let Some(c) = chars.next() {
+        match c {
+            '}' => return Ok(()),
+            '{' => {
+                result.push("(".to_string());
+                fill_from_latex(result, chars)?;
+                result.push(")".to_string());
+            },
+            '\\' => fill_from_command(result, chars)?,
+            '_' => {
+                let subscript = group_from_latex(chars)?;
+                let name = result.pop().ok_or("Subscript without a variable")?;
+                if !subscript.chars().all(|c| c.is_alphanumeric()) {
+                    Err("Unsupported subscript")?;
+                }
+                result.push(format!("{name}_{subscript}"));
+            },
+            'x' => result.push("@x".to_string()),
+            'y' => result.push("@y".to_string()),
+            'r' => result.push("@r".to_string()),
+            'e' => result.push("std.e".to_string()),
+            '^' => Err("Exponents are not supported by desmon")?,
+            '.' if chars.peek() == Some(&'.') => {
+                while chars.peek() == Some(&'.') {
+                    chars.next();
+                }
+                result.push("...".to_string());
+            },
+            c if c.is_ascii_digit() || c == '.' => {
+                let mut number = c.to_string();
+                while let Some(&c) = chars.peek() {
+                    if c.is_ascii_digit() || (c == '.' && !number.contains('.')) {
+                        number.push(c);
+                        chars.next();
+                    } else {
+                        break;
+                    }
+                }
+                result.push(number);
+            },
+            c if c.is_alphabetic() => result.push(c.to_string()),
+            c if c.is_whitespace() => (),
+            '+' | '-' | '=' | '<' | '>' | ',' | ':' | '(' | ')' | '[' | ']' | '|' | '!' | '/' => result.push(c.to_string()),
+            '*' => result.push("*".to_string()),
+            _ => Err("Unsupported character")?,
+        }
+    }
+    Ok(())
+}
+
+
+fn group_from_latex<I>(chars: &mut Peekable<I>) -> Result<String, &'static str>
+where I: Iterator<Item = char>
+{
+    match chars.next() {
+        Some('{') => {
+            let mut result = String::new();
+            for c in chars.by_ref() {
+                if c == '}' {
+                    return Ok(result);
+                }
+                result.push(c);
+            }
+            Err("Unclosed '{'")
+        },
+        Some(c) => Ok(c.to_string()),
+        None => Err("Expected a group"),
+    }
+}
+
+
+fn fill_from_command<I>(result: &mut Vec<String>, chars: &mut Peekable<I>) -> Result<(), &'static str>
+where I: Iterator<Item = char>
+{
+    let mut name = String::new();
+    while let Some(&c) = chars.peek() {
+        if c.is_alphabetic() {
+            name.push(c);
+            chars.next();
+        } else {
+            break;
+        }
+    }
+    if name.is_empty() {
+        match chars.next() {
+            Some('{') => result.push("{".to_string()),
+            Some('}') => result.push("}".to_string()),
+            Some(' ' | ',' | ';' | '!') => (),
+            _ => Err("Unsupported LaTeX escape")?,
+        }
+        return Ok(());
+    }
+    match name.as_str() {
+        "left" | "right" => {
+            match chars.next() {
+                Some('\\') => match chars.next() {
+                    Some(c @ ('{' | '}')) => result.push(c.to_string()),
+                    _ => Err("Unsupported bracket")?,
+                },
+                Some('.') => (),
+                Some(c) => result.push(c.to_string()),
+                None => Err("Unexpected end of LaTeX")?,
+            }
+        },
+        "cdot" | "times" => result.push("*".to_string()),
+        "le" => result.push("<=".to_string()),
+        "ge" => result.push(">=".to_string()),
+        "to" => result.push("->".to_string()),
+        "theta" => result.push("@t".to_string()),
+        "frac" => {
+            for (index, operator) in ["(", "/"].iter().enumerate() {
+                if index != 0 {
+                    result.push(")".to_string());
+                }
+                result.push(operator.to_string());
+                result.push("(".to_string());
+                if chars.next() != Some('{') {
+                    Err("Expected '{' after \\frac")?;
+                }
+                fill_from_latex(result, chars)?;
+            }
+            result.push("))".to_string());
+        },
+        "operatorname" => {
+            let name = group_from_latex(chars)?;
+            if STD_FUNCS.contains(&name.as_str()) {
+                result.push(format!("std.{name}"));
+            } else {
+                Err("Unsupported function")?;
+            }
+        },
+        name if STD_CONSTS.contains(&name) || STD_FUNCS.contains(&name) => result.push(format!("std.{name}")),
+        _ => Err("Unsupported LaTeX command")?,
+    }
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::import::import_state;
+    use crate::json::Json;
+
+    fn import(list: &str) -> String {
+        let state = format!(r#"{{"expressions":{{"list":[{list}]}}}}"#);
+        import_state(&Json::from_chars(&mut state.chars().peekable()).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn defines_folder_names_in_their_namespace() {
+        let list = r#"{"type":"expression","latex":"k=2","hidden":true},
+            {"type":"folder","id":"f","title":"shapes","collapsed":true},
+            {"type":"expression","folderId":"f","latex":"a=k+1","hidden":true},
+            {"type":"expression","folderId":"f","latex":"g\\left(t\\right)=t\\cdot a","hidden":true},
+            {"type":"expression","latex":"a<3","hidden":true}"#;
+        assert_eq!(import(list), "\
+            use shapes.a;\n\
+            use shapes.g;\n\
+            this.k = 2;\n\
+            namespace shapes {\n    \
+                this.a = k + 1;\n    \
+                this.g(t) = t * a;\n\
+            }\n\
+            a < 3;\n");
+    }
+}
@@ -0,0 +1,217 @@
+use std::fmt;
+use std::iter::Peekable;
+
+
+#[derive(Debug, Clone)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    Number(String),
+    String(String),
+    Array(Vec<Self>),
+    Object(Vec<(String, Self)>),
+} impl Json {
+    pub fn object() -> Self {
+        Self::Object(vec![])
+    }
+    pub fn string(value: &str) -> Self {
+        Self::String(value.to_string())
+    }
+    pub fn insert(&mut self, key: &str, value: Self) {
+        if let Self::Object(pairs) = self {
+            if let Some((_, v)) = pairs.iter_mut().find(|(k, _)| k == key) {
+                *v = value;
+            } else {
+                pairs.push((key.to_string(), value));
+            }
+        }
+    }
+    pub fn from_literal(value: &str) -> Self {
+        match value {
+            "null" => Self::Null,
+            "true" => Self::Bool(true),
+            "false" => Self::Bool(false),
+            _ => if value.parse::<f64>().is_ok() {
+                Self::Number(value.to_string())
+            } else {
+                Self::string(value)
+            },
+        }
+    }
+    pub fn get(&self, key: &str) -> Option<&Self> {
+        if let Self::Object(pairs) = self {
+            pairs.iter().find(|(k, _)| k == key).map(|(_, v)| v)
+        } else {
+            None
+        }
+    }
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Self::String(s) | Self::Number(s) => Some(s),
+            _ => None,
+        }
+    }
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Self::Bool(b) => Some(*b),
+            Self::String(s) => s.parse().ok(),
+            _ => None,
+        }
+    }
+    pub fn from_chars<I>(chars: &mut Peekable<I>) -> Result<Self, &'static str>
+    where I: Iterator<Item = char>
+    {
+        Self::skip_whitespace(chars);
+        let result = match chars.peek() {
+            Some('{') => {
+                chars.next();
+                let mut pairs = vec![];
+                Self::skip_whitespace(chars);
+                if let Some('}') = chars.peek() {
+                    chars.next();
+                    return Ok(Self::Object(pairs));
+                }
+                loop {
+                    Self::skip_whitespace(chars);
+                    let key = Self::string_from_chars(chars)?;
+                    Self::skip_whitespace(chars);
+                    if chars.next() != Some(':') {
+                        Err("Expected ':' in JSON object")?;
+                    }
+                    pairs.push((key, Self::from_chars(chars)?));
+                    Self::skip_whitespace(chars);
+                    match chars.next() {
+                        Some(',') => (),
+                        Some('}') => break,
+                        _ => Err("Expected ',' or '}' in JSON object")?,
+                    }
+                }
+                Self::Object(pairs)
+            },
+            Some('[') => {
+                chars.next();
+                let mut items = vec![];
+                Self::skip_whitespace(chars);
+                if let Some(']') = chars.peek() {
+                    chars.next();
+                    return Ok(Self::Array(items));
+                }
+                loop {
+                    items.push(Self::from_chars(chars)?);
+                    Self::skip_whitespace(chars);
+                    match chars.next() {
+                        Some(',') => (),
+                        Some(']') => break,
+                        _ => Err("Expected ',' or ']' in JSON array")?,
+                    }
+                }
+                Self::Array(items)
+            },
+            Some('"') => Self::String(Self::string_from_chars(chars)?),
+            Some(_) => {
+                let mut value = String::new();
+                while let Some(&c) = chars.peek() {
+                    if c.is_alphanumeric() || "+-.".contains(c) {
+                        value.push(c);
+                        chars.next();
+                    } else {
+                        break;
+                    }
+                }
+                match Self::from_literal(&value) {
+                    Self::String(_) => Err("Unexpected value in JSON")?,
+                    v => v,
+                }
+            },
+            None => Err("Unexpected end of JSON")?,
+        };
+        Ok(result)
+    }
+    fn skip_whitespace<I>(chars: &mut Peekable<I>)
+    where I: Iterator<Item = char>
+    {
+        while let Some(c) = chars.peek() {
+            if c.is_whitespace() {
+                chars.next();
+            } else {
+                break;
+            }
+        }
+    }
+    fn string_from_chars<I>(chars: &mut Peekable<I>) -> Result<String, &'static str>
+    where I: Iterator<Item = char>
+    {
+        if chars.next() != Some('"') {
+            Err("Expected a JSON string")?;
+        }
+        let mut result = String::new();
+        while let Some(c) = chars.next() {
+            match c {
+                '"' => return Ok(result),
+                '\\' => result.push(match chars.next() {
+                    Some('n') => '\n',
+                    Some('r') => '\r',
+                    Some('t') => '\t',
+                    Some('b') => '\u{8}',
+                    Some('f') => '\u{c}',
+                    Some('u') => {
+                        let code: String = chars.by_ref().take(4).collect();
+                        let code = u32::from_str_radix(&code, 16).map_err(|_| "Invalid JSON escape")?;
+                        char::from_u32(code).unwrap_or(char::REPLACEMENT_CHARACTER)
+                    },
+                    Some(c) => c,
+                    None => Err("Unclosed JSON string")?,
+                }),
+                c => result.push(c),
+            }
+        }
+        Err("Unclosed JSON string")
+    }
+    fn write_string(f: &mut fmt::Formatter, value: &str) -> fmt::Result {
+        write!(f, "\"")?;
+        for c in value.chars() {
+            match c {
+                '"' => write!(f, "\\\"")?,
+                '\\' => write!(f, "\\\\")?,
+                '\n' => write!(f, "\\n")?,
+                '\r' => write!(f, "\\r")?,
+                '\t' => write!(f, "\\t")?,
+                // Keeps the output safe to embed inside an HTML <script> tag.
+                '<' | '>' | '&' => write!(f, "\\u{:04x}", c as u32)?,
+                c if (c as u32) < 0x20 => write!(f, "\\u{:04x}", c as u32)?,
+                c => write!(f, "{c}")?,
+            }
+        }
+        write!(f, "\"")
+    }
+} impl fmt::Display for Json {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Null => write!(f, "null"),
+            Self::Bool(b) => write!(f, "{b}"),
+            Self::Number(n) => write!(f, "{n}"),
+            Self::String(s) => Self::write_string(f, s),
+            Self::Array(items) => {
+                write!(f, "[")?;
+                for (index, item) in items.iter().enumerate() {
+                    if index != 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{item}")?;
+                }
+                write!(f, "]")
+            },
+            Self::Object(pairs) => {
+                write!(f, "{{")?;
+                for (index, (key, value)) in pairs.iter().enumerate() {
+                    if index != 0 {
+                        write!(f, ",")?;
+                    }
+                    Self::write_string(f, key)?;
+                    write!(f, ":{value}")?;
+                }
+                write!(f, "}}")
+            },
+        }
+    }
+}
@@ -0,0 +1,353 @@
+//! The desmon compiler, which turns desmon code into Desmos graphing calculators.
+//!
+//! ```no_run
+//! let output = desmon::compile("graph: @y = @x;", &desmon::Options::default()).unwrap();
+//! println!("{}", output.calculator.get_html());
+//! ```
+//!
+//! Sources can also come from memory instead of the disk:
+//!
+//! ```
+//! use std::sync::Arc;
+//! use desmon::preprocessor::files::MemoryFileProvider;
+//!
+//! let mut files = MemoryFileProvider::new();
+//! files.insert("lib/shapes.dmn", "namespace shapes { this.r = 2; }");
+//! let options = desmon::Options { provider: Arc::new(files), ..Default::default() };
+//! assert!(desmon::compile("/#lib/shapes.dmn\ngraph: @r = shapes.r;", &options).is_ok());
+//! ```
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::fmt;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+pub mod ast;
+pub mod desmos;
+pub mod formatter;
+pub mod import;
+pub mod json;
+pub mod modules;
+pub mod preprocessor;
+
+use crate::ast::constants::fold_constants;
+use crate::ast::dead_code::eliminate_dead_code;
+use crate::ast::lexer::Token;
+use crate::ast::lints::{get_included_definitions, lint, Level, Lint};
+use crate::ast::uses::{expand_uses, resolve_names};
+use crate::ast::{AbstractSyntaxItem, AbstractSyntaxTree, AbstractSyntaxTreeTrait};
+use crate::desmos::{assign_ids, DesmosLine, DesmosName, FolderStrategy, GraphingCalculator};
+use crate::modules::ModuleLoader;
+use crate::preprocessor::{Preprocessor, SourceMap};
+use crate::preprocessor::files::{DiskFileProvider, FileProvider};
+
+
+#[derive(Debug, Clone)]
+pub struct Options {
+    /// Path of the compiled source, includes are resolved relative to it.
+    pub path: PathBuf,
+    /// Directories searched for `/#` includes not found next to the including file.
+    pub include_paths: Vec<PathBuf>,
+    /// Preprocessor definitions that are set before the source is read.
+    pub definitions: HashMap<String, String>,
+    /// Where the source and its includes are read from, the disk by default.
+    pub provider: Arc<dyn FileProvider>,
+    /// Whether definitions nothing on the graph depends on are left out.
+    pub eliminate_dead_code: bool,
+    /// Levels of the lints that do not just warn.
+    pub lints: HashMap<Lint, Level>,
+    /// Which namespaces get a folder of their own.
+    pub folders: FolderStrategy,
+} impl Options {
+    pub fn read_source(&self) -> Result<String, Diagnostics> {
+        let source = self.provider.read(&self.path);
+        Ok(source.map_err(|_| Diagnostic::from(format!("Could not read file '{}'", self.path.display())))?)
+    }
+} impl Default for Options {
+    fn default() -> Self {
+        Self {
+            path: PathBuf::from("main.dmn"),
+            include_paths: vec![],
+            definitions: HashMap::new(),
+            provider: Arc::new(DiskFileProvider),
+            eliminate_dead_code: true,
+            lints: HashMap::new(),
+            folders: FolderStrategy::default(),
+        }
+    }
+}
+
+
+#[derive(Debug)]
+pub struct Output {
+    pub calculator: GraphingCalculator,
+    /// Where each name ended up, for scripts that work with the calculator.
+    pub names: Vec<DesmosName>,
+    /// Every file read while compiling, starting with the source itself.
+    pub files: Vec<PathBuf>,
+    pub warnings: Diagnostics,
+}
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Location {
+    pub path: PathBuf,
+    pub line: usize,
+} impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.path.display(), self.line)
+    }
+}
+
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+    pub location: Option<Location>,
+    /// Extra context printed under the message, innermost first.
+    pub notes: Vec<String>,
+} impl Diagnostic {
+    /// Sets the location unless a more precise one was already set.
+    pub fn with_location(mut self, location: Location) -> Self {
+        self.location.get_or_insert(location);
+        self
+    }
+    pub fn with_note(mut self, note: String) -> Self {
+        self.notes.push(note);
+        self
+    }
+    pub fn into_warning(mut self) -> Self {
+        self.severity = Severity::Warning;
+        self
+    }
+} impl From<&str> for Diagnostic {
+    fn from(message: &str) -> Self {
+        Self::from(message.to_string())
+    }
+} impl From<String> for Diagnostic {
+    fn from(message: String) -> Self {
+        Self { severity: Severity::Error, message, location: None, notes: vec![] }
+    }
+} impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.severity == Severity::Warning {
+            write!(f, "warning: ")?;
+        }
+        if let Some(location) = &self.location {
+            write!(f, "{location}: ")?;
+        }
+        write!(f, "{}", self.message)?;
+        for note in &self.notes {
+            write!(f, "\n    {note}")?;
+        }
+        Ok(())
+    }
+}
+
+
+#[derive(Debug, Clone, Default)]
+pub struct Diagnostics(pub Vec<Diagnostic>);
+impl From<&str> for Diagnostics {
+    fn from(message: &str) -> Self {
+        Self(vec![Diagnostic::from(message)])
+    }
+} impl From<Diagnostic> for Diagnostics {
+    fn from(diagnostic: Diagnostic) -> Self {
+        Self(vec![diagnostic])
+    }
+} impl fmt::Display for Diagnostics {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (index, diagnostic) in self.0.iter().enumerate() {
+            if index != 0 {
+                writeln!(f)?;
+            }
+            write!(f, "{diagnostic}")?;
+        }
+        Ok(())
+    }
+} impl From<Diagnostics> for String {
+    fn from(diagnostics: Diagnostics) -> Self {
+        diagnostics.to_string()
+    }
+}
+
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Stage {
+    Preprocess,
+    Expand,
+    Tokens,
+    Ast,
+    Lines,
+} impl Stage {
+    pub fn from_string(string: &str) -> Option<Self> {
+        match string {
+            "preprocess" => Some(Self::Preprocess),
+            "expand" => Some(Self::Expand),
+            "tokens" => Some(Self::Tokens),
+            "ast" => Some(Self::Ast),
+            "lines" => Some(Self::Lines),
+            _ => None,
+        }
+    }
+}
+
+
+/// Compiles desmon code into a calculator ready to be rendered.
+pub fn compile(source: &str, options: &Options) -> Result<Output, Diagnostics> {
+    compile_with_files(source, options, &mut vec![])
+}
+
+
+/// Compiles like `compile`, adding every file it reads to `files` as it goes,
+/// so a watcher still knows about an included file that fails to compile.
+pub fn compile_with_files(source: &str, options: &Options, files: &mut Vec<PathBuf>) -> Result<Output, Diagnostics> {
+    let (chars, source_map) = preprocess(source, options, files)?;
+    let (ast, warnings) = build_tree(&chars, &source_map, options, files)?;
+    let names = DesmosName::vec_from_ast(&ast);
+    let lines = DesmosLine::vec_from_ast(ast, options.folders);
+    Ok(Output {
+        calculator: GraphingCalculator::from(lines),
+        names,
+        files: files.to_vec(),
+        warnings,
+    })
+}
+
+
+/// Runs the compiler up to `stage` and returns a readable dump of its result.
+pub fn dump(source: &str, options: &Options, stage: Stage) -> Result<String, Diagnostics> {
+    let mut files = vec![];
+    let (chars, source_map) = preprocess(source, options, &mut files)?;
+    match stage {
+        Stage::Preprocess => return Ok(chars),
+        Stage::Expand => return Ok(source_map.get_trace()),
+        Stage::Tokens => return Ok(format!("{:?}", lex(&chars, &source_map)?.0)),
+        _ => (),
+    }
+    let (ast, _) = build_tree(&chars, &source_map, options, &mut files)?;
+    if stage == Stage::Ast {
+        return Ok(format!("{ast:#?}"));
+    }
+    Ok(format!("{:#?}", DesmosLine::vec_from_ast(ast, options.folders)))
+}
+
+
+/// Preprocesses the source, adding every file it reads to `files`.
+fn preprocess(source: &str, options: &Options, files: &mut Vec<PathBuf>) -> Result<(String, SourceMap), Diagnostics> {
+    let mut preprocessor = Preprocessor::new(options.provider.as_ref(), options.definitions.clone());
+    preprocessor.include_paths = options.include_paths.to_vec();
+    preprocessor.files.push(options.path.to_path_buf());
+    let chars = preprocessor.preprocess_source(source, &options.path);
+    files.append(&mut preprocessor.files);
+    Ok((chars?, preprocessor.source_map))
+}
+
+
+/// Turns preprocessed code into a tree with every name resolved and every id
+/// assigned, returning it with the lint warnings.
+fn build_tree(chars: &str, source_map: &SourceMap, options: &Options, files: &mut Vec<PathBuf>) -> Result<(AbstractSyntaxTree, Diagnostics), Diagnostics> {
+    let mut ast = parse(chars, source_map)?;
+    let mut libraries = link_modules(&mut ast, options, files)?;
+    libraries.extend(get_included_definitions(&ast, &options.path));
+    expand_uses(&mut ast)?;
+    let warnings = check_lints(&ast, &libraries, options)?;
+    resolve_names(&mut ast)?;
+    fold_constants(&mut ast)?;
+    if options.eliminate_dead_code {
+        eliminate_dead_code(&mut ast);
+    }
+    assign_ids(&mut ast, options.folders).map_err(Diagnostic::from)?;
+    Ok((ast, warnings))
+}
+
+
+/// Lexes and parses preprocessed code, pointing errors back at the line and
+/// the definitions they came from, and marking items with where they start.
+pub(crate) fn parse(chars: &str, source_map: &SourceMap) -> Result<AbstractSyntaxTree, Diagnostic> {
+    let (tokens, offsets) = lex(chars, source_map)?;
+    let mut iter = tokens.iter().peekable();
+    let mut ast = AbstractSyntaxTree::from_tokens(&mut iter, false).map_err(|e| {
+        // The parser fails right after reading the token it did not expect.
+        let index = (tokens.len() - iter.len()).saturating_sub(1);
+        source_map.get_diagnostic(e.into(), offsets.get(index).copied().unwrap_or(chars.len()))
+    })?;
+    locate_items(&mut ast, &offsets, source_map);
+    Ok(ast)
+}
+
+
+/// Replaces the token counts the parser marks items with by their locations.
+fn locate_items(ast: &mut AbstractSyntaxTree, offsets: &[usize], source_map: &SourceMap) {
+    type A = AbstractSyntaxItem;
+    for item in ast.iter_mut() {
+        match item {
+            A::TokensLeft(left) => {
+                let offset = offsets.len().checked_sub(*left).and_then(|index| offsets.get(index));
+                if let Some(location) = offset.and_then(|&offset| source_map.get_location(offset)) {
+                    *item = A::Location(location.clone());
+                }
+            },
+            A::Namespace(_, items, _) => locate_items(items, offsets, source_map),
+            _ => (),
+        }
+    }
+}
+
+
+/// Lexes preprocessed code into tokens and the byte offset each of them starts at.
+fn lex(chars: &str, source_map: &SourceMap) -> Result<(Vec<Token>, Vec<usize>), Diagnostic> {
+    let offset = Cell::new(0);
+    let mut iter = chars.chars().inspect(|c| offset.set(offset.get() + c.len_utf8())).peekable();
+    let mut tokens = vec![];
+    let mut offsets = vec![];
+    while let Some(c) = iter.peek() {
+        // The peeked char was already counted.
+        let start = offset.get() - c.len_utf8();
+        offsets.push(start);
+        tokens.push(Token::from_chars(&mut iter).map_err(|e| source_map.get_diagnostic(e.into(), start))?);
+    }
+    Ok((tokens, offsets))
+}
+
+
+/// Runs the lints, failing with every finding if any of them is denied.
+fn check_lints(ast: &AbstractSyntaxTree, libraries: &[Vec<String>], options: &Options) -> Result<Diagnostics, Diagnostics> {
+    let mut errors = vec![];
+    let mut warnings = vec![];
+    for (lint, message, location) in lint(ast, libraries) {
+        let mut diagnostic = Diagnostic::from(format!("{message} [{}]", lint.get_name()));
+        diagnostic.location = location;
+        match options.lints.get(&lint).copied().unwrap_or_default() {
+            Level::Allow => (),
+            Level::Warn => warnings.push(diagnostic.into_warning()),
+            Level::Deny => errors.push(diagnostic),
+        }
+    }
+    if errors.is_empty() {
+        Ok(Diagnostics(warnings))
+    } else {
+        errors.append(&mut warnings);
+        Err(Diagnostics(errors))
+    }
+}
+
+
+/// Adds the imported modules to the tree and returns their import paths.
+fn link_modules(ast: &mut AbstractSyntaxTree, options: &Options, files: &mut Vec<PathBuf>) -> Result<Vec<Vec<String>>, Diagnostic> {
+    let mut loader = ModuleLoader::new(options);
+    let result = loader.load_imports(ast);
+    files.append(&mut loader.files);
+    result?;
+    let names = loader.names.to_vec();
+    loader.link(ast);
+    Ok(names)
+}
@@ -1,229 +1,228 @@
-use std::env::args;
 use std::collections::HashMap;
+use std::env::{args, split_paths, var_os};
+use std::fs::{create_dir_all, metadata, read_to_string, remove_file, rename, write};
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::process::exit;
+use std::sync::Arc;
+use std::thread::sleep;
+use std::time::{Duration, SystemTime};
 
-mod preprocessor;
-use crate::preprocessor::preprocess;
+use desmon::formatter::format;
+use desmon::import::import_state;
+use desmon::desmos::{DesmosName, FolderStrategy, GraphingCalculator};
+use desmon::json::Json;
+use desmon::ast::lints::{Level, Lint};
+use desmon::{compile, compile_with_files, dump, Diagnostics, Options, Stage};
 
-mod ast;
-use crate::ast::lexer::Token;
-use crate::ast::{AbstractSyntaxItem, AbstractSyntaxTree, AbstractSyntaxTreeTrait, ExpressionItem, Variable};
+mod cli;
+use crate::cli::{get_help, Arguments, Command};
 
+mod server;
+use crate::server::LiveServer;
 
-#[derive(Debug)]
-struct DesmosExpression {
-    latex: String,
-    folder_id: Option<String>,
-    opacity: Option<String>,
-    color_latex: Option<String>,
+
+fn write_output<P: AsRef<Path>>(path: P, contents: &str) -> Result<(), &'static str> {
+    let path = path.as_ref();
+    let file_name = path.file_name().ok_or("Output path is not a file")?;
+    let parent = path.parent().unwrap_or(Path::new(""));
+    if !parent.as_os_str().is_empty() {
+        create_dir_all(parent).map_err(|_| "Could not create output directory")?;
+    }
+    // Write next to the target and rename, so readers never see a half written file.
+    let mut temp_name = OsString::from(".");
+    temp_name.push(file_name);
+    temp_name.push(".tmp");
+    let temp_path = parent.join(temp_name);
+    write(&temp_path, contents).map_err(|_| "Could not write output file")?;
+    rename(&temp_path, path).map_err(|_| {
+        let _ = remove_file(&temp_path);
+        "Could not write output file"
+    })
 }
 
 
-#[derive(Debug)]
-enum DesmosLine {
-    Expression(DesmosExpression),
-    Folder(String),
-    Text(String, Option<String>),
-} impl DesmosLine {
-    pub fn vec_from_ast(ast: AbstractSyntaxTree) -> Vec<Self> {
-        let mut result = vec![];
-        let ids = &ast.get_variable_ids();
-        let usespace = &mut HashMap::new();
-        Self::fill_from_ast(&mut result, ast, vec![], usespace, ids);
-        result
+fn read_source(arguments: &Arguments) -> Result<(String, Options), String> {
+    let path = arguments.get_path().map(String::as_str).unwrap_or("");
+    let mut include_paths: Vec<PathBuf> = arguments.get_all("include").into_iter().map(PathBuf::from).collect();
+    if let Some(paths) = var_os("DESMON_PATH") {
+        include_paths.extend(split_paths(&paths).filter(|p| !p.as_os_str().is_empty()));
     }
-    pub fn fill_from_ast(vec: &mut Vec<Self>, ast: AbstractSyntaxTree, namespaces: Vec<String>, usespace: &mut HashMap<String, Variable>, ids: &HashMap<Vec<String>, usize>) {
-        type T = AbstractSyntaxItem;
-        let temp = namespaces.join(".");
-        let mut folders = vec![];
-        let mut current_uses = vec![];
-        for i in ast {
-            match i {
-                T::Expression(e) => vec.push(Self::Expression(DesmosExpression {
-                    latex: ExpressionItem::vec_to_latex(e, namespaces.to_vec(), usespace, ids),
-                    folder_id: if temp.is_empty() { None } else { Some(temp.to_string()) }, 
-                    opacity: None,
-                    color_latex: None,
-                })),
-                T::Graph(c, opacity, e) => {
-                    let color = if let Some(c) = c { 
-                        c.get_latex(&namespaces, usespace, ids)
-                    } else { 
-                        String::new() 
-                    };
-                    vec.push(Self::Expression(DesmosExpression {
-                        latex: ExpressionItem::vec_to_latex(e, namespaces.to_vec(), usespace, ids),
-                        folder_id: if temp.is_empty() { None } else { Some(temp.to_string()) }, 
-                        opacity,
-                        color_latex: Some(color),
-                    }));
-                },
-                T::Namespace(name, e) => {
-                    let mut names = namespaces.to_vec();
-                    names.push(name.to_string());
-                    folders.push(Self::Folder(names.join(".")));
-                    Self::fill_from_ast(&mut folders, e, names, usespace, ids);
-                },
-                T::Text(t) => vec.push(Self::Text(t, if temp.is_empty() { None } else { Some(temp.to_string()) })),
-                T::Use(ExpressionItem::Variable(v)) => {
-                    match v {
-                        Variable::Absolute(is) => {
-                            let mut key = String::new();
-                            let mut value = vec![];
-                            for i in is {
-                                if !key.is_empty() {
-                                    value.push(key.to_string());
-                                }
-                                key = i;
-                            }
-                            usespace.insert(key.to_string(), Variable::Absolute(value));
-                            current_uses.push(key.to_string());
-                        },
-                        Variable::Relative(is) => {
-                            let mut key = String::new();
-                            let mut value = vec![];
-                            for i in is {
-                                if !key.is_empty() {
-                                    value.push(key.to_string());
-                                }
-                                key = i;
-                            }
-                            usespace.insert(key.to_string(), Variable::Relative(value));
-                            current_uses.push(key.to_string());
-                        },
-                        Variable::Std(name) => {
-                            usespace.insert(name.to_string(), Variable::Std(String::new()));  
-                            current_uses.push(name.to_string());
-                        },
-                    }
-                },
-                _ => (),
-            }
-        }
-        vec.append(&mut folders);
-        for i in current_uses {
-            usespace.remove(&i);
-        }
+    let mut definitions = HashMap::new();
+    for definition in arguments.get_all("define") {
+        let (key, value) = definition.split_once('=').unwrap_or((definition, "1"));
+        definitions.insert(key.to_string(), value.to_string());
     }
-    fn get_desmos_object_js(&self) -> HashMap<&'static str, String> {
-        match self {
-            Self::Expression(e) => {
-                let mut result = HashMap::from([
-                    ("type", "expression".to_string()),
-                    ("latex", e.latex.to_string()),
-                ]);
-                if let Some(i) = &e.folder_id {
-                    result.insert("folderId", i.to_string());
-                }
-                if let Some(c) = &e.color_latex {
-                    result.insert("colorLatex", c.to_string());
-                } else {
-                    result.insert("hidden", "true".to_string());
-                }
-                if let Some(o) = &e.opacity {
-                    result.insert("fillOpacity", o.to_string());
-                }
-                result
-            },
-            Self::Folder(title) => HashMap::from([
-                ("type", "folder".to_string()),
-                ("title", title.to_string()),
-                ("id", title.to_string()),
-                ("collapsed", "true".to_string()),
-            ]),
-            Self::Text(text, folder_id) => {
-                let mut result = HashMap::from([
-                    ("type", "text".to_string()),
-                    ("text", text.to_string()),
-                ]);
-                if let Some(i) = folder_id {
-                    result.insert("folderId", i.to_string());
+    // 'all' goes first so a named lint always overrides it.
+    let mut lints = HashMap::new();
+    for is_all in [true, false] {
+        for (option, level) in [("allow", Level::Allow), ("warn", Level::Warn), ("deny", Level::Deny)] {
+            for name in arguments.get_all(option) {
+                if name == "all" && is_all {
+                    lints.extend(Lint::get_all().map(|l| (l, level)));
+                } else if name != "all" && !is_all {
+                    let lint = Lint::from_string(name).ok_or_else(|| {
+                        let names: Vec<&str> = Lint::get_all().iter().map(Lint::get_name).collect();
+                        format!("Unknown lint '{name}', one of all, {}", names.join(", "))
+                    })?;
+                    lints.insert(lint, level);
                 }
-                result
-            },
+            }
         }
     }
+    let folders = match arguments.get("folders") {
+        Some(strategy) => FolderStrategy::from_string(strategy)
+            .ok_or(format!("Unknown folder strategy '{strategy}', one of every, top, none or a depth"))?,
+        None => FolderStrategy::default(),
+    };
+    let options = Options {
+        path: PathBuf::from(path),
+        include_paths,
+        definitions,
+        eliminate_dead_code: !arguments.has("keep-unused"),
+        lints,
+        folders,
+        ..Options::default()
+    };
+    Ok((options.read_source()?, options))
 }
 
 
-struct GraphingCalculator {
-    expressions: Vec<DesmosLine>, 
-    api_key: String,
-} impl GraphingCalculator {
-    pub fn from(expressions: Vec<DesmosLine>) -> Self {
-        Self {
-            expressions,
-            api_key: "dcb31709b452b1cf9dc26972add0fda6".to_string(),
-        }
+fn print_output(arguments: &Arguments, contents: &str) -> Result<(), &'static str> {
+    if let Some(output) = arguments.get("output") {
+        write_output(output, contents)
+    } else {
+        print!("{contents}");
+        Ok(())
     }
-    pub fn print_html(&self) {
-        let api_link = self.get_api_link();
-        println!(r"<!DOCTYPE html>
-<html style='height: 100%;'>
-<body style='height: 100%; margin: 0%'>
-<script src='{api_link}'></script>
-<div id='calculator' style='width: 100%; height: 100%;'></div>
-<script>
-    var elt = document.getElementById('calculator');
-    var calculator = Desmos.GraphingCalculator(elt);
-    calculator.setState({{
-        version: 9,
-        expressions: {{ list: [");
-        for expr in &self.expressions {
-            println!("{:?},", expr.get_desmos_object_js());
-        }
-        println!("
-        ]}},
-        graph: {{ 
-            showGrid: false, 
-            showXAxis: false, 
-            showYAxis: false,
-            xAxisNumbers: false,
-            yAxisNumbers: false,
-        }},
-    }})");
-        println!("</script>");
-        println!("</body>");
-        println!("</html>");
+}
+
+
+fn render(arguments: &Arguments, files: &mut Vec<PathBuf>) -> Result<GraphingCalculator, String> {
+    let path = arguments.get_path().map(String::as_str).unwrap_or("");
+    files.push(PathBuf::from(path));
+    let (source, options) = read_source(arguments)?;
+    // Files read before an error are watched too, so fixing them rebuilds.
+    let mut read = vec![];
+    let output = compile_with_files(&source, &options, &mut read);
+    files.extend(read.into_iter().skip(1));
+    let output = output?;
+    print_warnings(&output.warnings);
+    if let Some(manifest) = arguments.get("manifest") {
+        write_output(manifest, &DesmosName::get_manifest_file(&output.names, Path::new(manifest)))?;
+    }
+    let mut calc = output.calculator;
+    if let Some(template) = arguments.get("template") {
+        files.push(PathBuf::from(template));
+        calc.template = read_to_string(template).map_err(|_| "Could not read template")?;
     }
-    fn get_api_link(&self) -> String {
-        let url_start = "https://www.desmos.com/api/v1.7/calculator.js?apiKey=";
-        format!("{url_start}{}", self.api_key)
+    for option in arguments.get_all("option") {
+        let (key, value) = option.split_once('=').ok_or("Calculator options are written as key=value")?;
+        calc.options.insert(key, Json::from_literal(value));
     }
+    Ok(calc)
 }
 
 
-fn cli(options: Vec<String>) -> Result<(), &'static str> {
-    let mut definitions = HashMap::new();
-    if let Some(path) = options.get(1) {
-        let chars = preprocess(path, &mut definitions)?;
-        if options.contains(&"--preprocess".to_string()) {
-            eprintln!("{chars}");
-        }
-        let tokens = Token::vec_from_chars(&mut chars.chars().peekable())?;
-        if options.contains(&"--tokens".to_string()) {
-            eprintln!("{tokens:?}"); 
-        }
-        let ast = AbstractSyntaxTree::from_tokens(&mut tokens.iter().peekable(), false)?;
-        if options.contains(&"--ast".to_string()) {
-            eprintln!("{ast:#?}"); 
+fn print_warnings(warnings: &Diagnostics) {
+    for warning in &warnings.0 {
+        eprintln!("\x1b[33m{warning}\x1b[0m");
+    }
+}
+
+
+fn get_modified_times(files: &[PathBuf]) -> Vec<Option<SystemTime>> {
+    files.iter().map(|f| metadata(f).and_then(|m| m.modified()).ok()).collect()
+}
+
+
+/// Rerenders whenever the main file, an included file or the template changes.
+/// Errors are reported and the watch continues with the files seen so far.
+fn watch<F>(arguments: &Arguments, mut on_render: F) -> Result<(), String>
+where F: FnMut(GraphingCalculator) -> Result<(), String>
+{
+    loop {
+        let mut files = vec![];
+        if let Err(e) = render(arguments, &mut files).and_then(&mut on_render) {
+            eprintln!("\x1b[31m{e}\x1b[0m");
         }
-        let lines = DesmosLine::vec_from_ast(ast);
-        if options.contains(&"--lines".to_string()) {
-            eprintln!("{lines:#?}"); 
+        let times = get_modified_times(&files);
+        while get_modified_times(&files) == times {
+            sleep(Duration::from_millis(250));
         }
-        let calc = GraphingCalculator::from(lines);
-        calc.print_html();
-    } else {
-        println!("----The-Desmon-Compiler----");
-        println!("Compile Desmon code into an HTML file by running:");
-        println!(">>> cargo run [path_to_file] > [path_to_html_output_file]");
+    }
+}
+
+
+fn cli(arguments: Arguments) -> Result<(), String> {
+    let path = arguments.get_path().map(String::as_str).unwrap_or("");
+    match arguments.command {
+        Command::Help => print!("{}", get_help(arguments.get_path().map(|c| {
+            Command::from_string(c).ok_or(format!("Unknown command '{c}'"))
+        }).transpose()?)),
+        Command::Version => println!("desmon {}", env!("CARGO_PKG_VERSION")),
+        Command::Build => if arguments.has("watch") {
+            let output = arguments.get("output").ok_or("'--watch' needs an '--output' file")?;
+            watch(&arguments, |calc| {
+                write_output(output, &calc.get_html())?;
+                eprintln!("Built {output}");
+                Ok(())
+            })?;
+        } else {
+            print_output(&arguments, &render(&arguments, &mut vec![])?.get_html())?;
+        },
+        Command::Serve => {
+            let address = format!("127.0.0.1:{}", arguments.get("port").map(String::as_str).unwrap_or("8080"));
+            let server = Arc::new(LiveServer::default());
+            Arc::clone(&server).listen(&address)?;
+            eprintln!("Serving on http://{address}");
+            watch(&arguments, |calc| {
+                server.update(calc.get_html(), calc.get_state().to_string());
+                eprintln!("Rendered {path}");
+                Ok(())
+            })?;
+        },
+        Command::Check => {
+            let (source, options) = read_source(&arguments)?;
+            print_warnings(&compile(&source, &options)?.warnings);
+        },
+        Command::Dump => {
+            let stage = if arguments.has("expand") {
+                Stage::Expand
+            } else {
+                let stage = arguments.get("stage").ok_or("Missing '--stage', one of preprocess, expand, tokens, ast or lines")?;
+                Stage::from_string(stage)
+                    .ok_or(format!("Unknown stage '{stage}', one of preprocess, expand, tokens, ast or lines"))?
+            };
+            let (source, options) = read_source(&arguments)?;
+            print_output(&arguments, &format!("{}\n", dump(&source, &options, stage)?))?;
+        },
+        Command::Fmt => {
+            let source = read_to_string(path).map_err(|_| "Could not read file")?;
+            let formatted = format(&source);
+            if arguments.has("check") {
+                if formatted != source {
+                    Err(format!("'{path}' is not formatted"))?;
+                }
+            } else if let Some(output) = arguments.get("output") {
+                write_output(output, &formatted)?;
+            } else if formatted != source {
+                write_output(path, &formatted)?;
+            }
+        },
+        Command::Import => {
+            let source = read_to_string(path).map_err(|_| "Could not read file")?;
+            let state = Json::from_chars(&mut source.chars().peekable())?;
+            print_output(&arguments, &import_state(&state)?)?;
+        },
     }
     Ok(())
 }
 
 
 fn main() {
-    if let Err(e) = cli(args().collect()) {
+    if let Err(e) = Arguments::from_args(args().collect()).and_then(cli) {
         eprintln!("\x1b[31m{e}\x1b[0m");
+        exit(1);
     }
 }
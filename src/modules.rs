@@ -0,0 +1,132 @@
+use std::collections::HashSet;
+use std::path::PathBuf;
+
+use crate::ast::{AbstractSyntaxItem, AbstractSyntaxTree, ExpressionItem, NamespaceAttributes, UseBinding, Variable};
+use crate::preprocessor::Preprocessor;
+use crate::{parse, Diagnostic, Options};
+
+
+/// Loads the files behind `import` statements. Every module is preprocessed
+/// with its own definitions, parsed once, and placed in the namespace named by
+/// its import path, so `import geometry.shapes;` reads `geometry/shapes.dmn`
+/// into `namespace geometry { namespace shapes { ... } }`. Module paths are
+/// looked up next to the compiled file and then in the include paths.
+pub struct ModuleLoader<'a> {
+    options: &'a Options,
+    loaded: HashSet<PathBuf>,
+    modules: AbstractSyntaxTree,
+    /// Every file read while loading modules.
+    pub files: Vec<PathBuf>,
+    /// The import path of every module loaded.
+    pub names: Vec<Vec<String>>,
+} impl<'a> ModuleLoader<'a> {
+    pub fn new(options: &'a Options) -> Self {
+        Self { options, loaded: HashSet::new(), modules: vec![], files: vec![], names: vec![] }
+    }
+    /// Loads every module imported in `ast` and turns each `import` into a
+    /// `use` of the module, so its last name can be used directly.
+    pub fn load_imports(&mut self, ast: &mut AbstractSyntaxTree) -> Result<(), Diagnostic> {
+        type A = AbstractSyntaxItem;
+        for item in ast.iter_mut() {
+            match item {
+                A::Import(names) => {
+                    self.load(names).map_err(|e| format!("In module '{}': {e}", names.join(".")))?;
+                    *item = A::Use(ExpressionItem::Variable(Variable::Absolute(names.to_vec())), UseBinding::Name);
+                },
+                A::Namespace(_, items, _) => self.load_imports(items)?,
+                _ => (),
+            }
+        }
+        Ok(())
+    }
+    /// Adds the loaded modules to the importing tree. A module goes inside the
+    /// namespaces the tree already declares with the same names, so importing
+    /// `geometry.shapes` next to a local `namespace geometry` makes one folder.
+    pub fn link(self, ast: &mut AbstractSyntaxTree) {
+        Self::merge(ast, self.modules);
+    }
+    fn load(&mut self, names: &[String]) -> Result<(), Diagnostic> {
+        let mut preprocessor = Preprocessor::new(self.options.provider.as_ref(), self.options.definitions.clone());
+        preprocessor.include_paths = self.options.include_paths.to_vec();
+        let parent = self.options.path.parent().ok_or("Could not get parent dir")?;
+        let path = preprocessor.find_file(parent, &format!("{}.dmn", names.join("/")))?;
+        if !self.loaded.insert(preprocessor.get_key(&path)) {
+            return Ok(());
+        }
+        let chars = preprocessor.preprocess(&path);
+        self.files.append(&mut preprocessor.files);
+        let mut ast = parse(&chars?, &preprocessor.source_map)?;
+        self.load_imports(&mut ast)?;
+        self.names.push(names.to_vec());
+        Self::insert(&mut self.modules, names, ast);
+        Ok(())
+    }
+    fn insert(tree: &mut AbstractSyntaxTree, names: &[String], ast: AbstractSyntaxTree) {
+        let mut items = ast;
+        let mut is_module = true;
+        for name in names.iter().rev() {
+            let attributes = NamespaceAttributes { is_module, ..NamespaceAttributes::default() };
+            items = vec![AbstractSyntaxItem::Namespace(name.to_string(), items, attributes)];
+            is_module = false;
+        }
+        Self::merge(tree, items);
+    }
+    fn merge(tree: &mut AbstractSyntaxTree, items: AbstractSyntaxTree) {
+        type A = AbstractSyntaxItem;
+        // A namespace with `pub` members would hide a module that is not `pub` too.
+        let is_sealed = tree.iter().any(|i| matches!(i, A::Public(_)));
+        for item in items {
+            let A::Namespace(name, items, attributes) = item else {
+                tree.push(item);
+                continue;
+            };
+            let existing = tree.iter_mut().find_map(|i| match i {
+                A::Namespace(n, items, attributes) if *n == name => Some((items, attributes)),
+                _ => None,
+            });
+            if let Some((existing, existing_attributes)) = existing {
+                existing_attributes.is_module |= attributes.is_module;
+                Self::merge(existing, items);
+            } else {
+                if is_sealed {
+                    tree.push(A::Public(ExpressionItem::Variable(Variable::Relative(vec![name.to_string()]))));
+                }
+                tree.push(A::Namespace(name, items, attributes));
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::desmos::DesmosLine;
+    use crate::preprocessor::files::MemoryFileProvider;
+    use crate::{compile, Options};
+
+    /// The value of every expression compiled from "main.dmn" and the modules next to it.
+    fn compile_files(files: &[(&str, &str)]) -> Result<Vec<String>, String> {
+        let mut provider = MemoryFileProvider::new();
+        for (path, contents) in files {
+            provider.insert(path, contents);
+        }
+        let options = Options { path: "main.dmn".into(), provider: Arc::new(provider), ..Options::default() };
+        let source = options.read_source().map_err(|e| e.to_string())?;
+        let output = compile(&source, &options).map_err(|e| e.to_string())?;
+        Ok(output.calculator.expressions.iter().filter_map(|line| match line {
+            DesmosLine::Expression(e) => e.latex.split_once('=').map(|(_, value)| value.to_string()),
+            _ => None,
+        }).collect())
+    }
+
+    #[test]
+    fn modules_do_not_see_the_uses_of_the_importer() {
+        let values = compile_files(&[
+            ("main.dmn", "const this.k = 3;\nnamespace other { const this.k = 1; }\nuse other.k;\nimport shapes;\ngraph: @y = shapes.r + k;"),
+            ("shapes.dmn", "this.r = k;"),
+        ]).unwrap();
+        assert_eq!(values, ["a+1", "3"]);
+    }
+}
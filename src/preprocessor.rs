@@ -1,63 +1,494 @@
-use std::collections::HashMap;
+use std::cell::Cell;
+use std::collections::{HashMap, HashSet};
 use std::iter::Peekable;
-use std::fs::read_to_string;
-use std::path::Path;
+use std::mem::take;
+use std::path::{Path, PathBuf};
 use std::ffi::OsStr;
 
+pub mod files;
+use crate::preprocessor::files::{normalize, FileProvider};
+use crate::{Diagnostic, Location};
 
-pub fn preprocess<P: AsRef<Path> + AsRef<OsStr>>(path: P, definitions: &mut HashMap<String, String>) -> Result<String, &'static str>
-{
-    let path = Path::new(&path);
-    let contents = read_to_string(path).map_err(|_| "Could not read file")?;
-    let mut result = String::new();
-    let chars = &mut contents.chars().peekable();
-    while let Some(c) = chars.next() {
-        match c {
-            '/' => if let Some(c) = chars.next() {
-                match c {
-                    '/' => preprocess_comment(chars)?,
-                    '*' => preprocess_multiline_comment(chars)?,
-                    '=' => preprocess_set_definition(chars, definitions)?,
-                    '#' => add_file(chars, path.parent().ok_or("Could not get parent dir")?, definitions, &mut result)?,
-                    c => {
-                        result.push('/');
-                        result.push(c);
-                    },
-                }
-                // result.push('\n');
+
+/// The deepest a definition may expand other definitions, which stops
+/// definitions that expand themselves.
+const MAX_EXPANSION_DEPTH: usize = 64;
+
+
+/// Arguments of the definition being expanded, by parameter name.
+type Scope = HashMap<String, String>;
+
+
+#[derive(Debug, Clone)]
+pub struct Definition {
+    /// Declared parameter names, or `None` for a definition that takes its
+    /// arguments positionally as `?0`, `?1`, ...
+    pub params: Option<Vec<String>>,
+    pub value: String,
+    /// Where the definition was written, `None` when it was set with `-D`.
+    pub location: Option<Location>,
+}
+
+
+/// One expansion of a definition, as listed by `desmon dump --stage expand`.
+#[derive(Debug, Clone)]
+pub struct Expansion {
+    pub name: String,
+    /// The call as it was written, like `?add(1; 2)`.
+    pub call: String,
+    pub value: String,
+    /// The line of the call, or of the definition that made it.
+    pub called_at: Location,
+    pub defined_at: Option<Location>,
+    /// Where the value ended up in the preprocessed code. Expansions inside
+    /// arguments have none, their values are moved wherever the argument is used.
+    pub span: Option<(usize, usize)>,
+    /// How many expansions this one is nested in.
+    pub depth: usize,
+} impl Expansion {
+    pub fn get_note(&self) -> String {
+        match &self.defined_at {
+            Some(location) => format!("in expansion of ?{} defined at {location}", self.name),
+            None => format!("in expansion of ?{} defined on the command line", self.name),
+        }
+    }
+}
+
+
+/// Maps offsets in the preprocessed code back to the lines and expansions they came from.
+#[derive(Debug, Clone, Default)]
+pub struct SourceMap {
+    /// Offsets where the code of a source line starts, in order.
+    pub origins: Vec<(usize, Location)>,
+    /// Every expansion, each one before those nested in it.
+    pub expansions: Vec<Expansion>,
+} impl SourceMap {
+    pub fn get_location(&self, offset: usize) -> Option<&Location> {
+        let index = self.origins.partition_point(|(start, _)| *start <= offset);
+        self.origins.get(index.checked_sub(1)?).map(|(_, location)| location)
+    }
+    /// The expansions that produced the code at `offset`, innermost first.
+    pub fn get_expansions(&self, offset: usize) -> Vec<&Expansion> {
+        self.expansions
+            .iter()
+            .rev()
+            .filter(|e| e.span.is_some_and(|(start, end)| start <= offset && offset < end))
+            .collect()
+    }
+    /// Points a diagnostic about the preprocessed code at `offset` back at its source.
+    pub fn get_diagnostic(&self, diagnostic: Diagnostic, offset: usize) -> Diagnostic {
+        let mut result = diagnostic;
+        if let Some(location) = self.get_location(offset) {
+            result = result.with_location(location.clone());
+        }
+        for expansion in self.get_expansions(offset) {
+            result = result.with_note(expansion.get_note());
+        }
+        result
+    }
+    /// Lists every expansion step, nested ones indented under their caller.
+    pub fn get_trace(&self) -> String {
+        let mut result = String::new();
+        for e in &self.expansions {
+            let indent = "    ".repeat(e.depth);
+            let value = e.value.replace('\n', "\\n");
+            result.push_str(&format!("{indent}{}: {} => {value}\n", e.called_at, e.call));
+        }
+        result
+    }
+    fn mark(&self) -> (usize, usize) {
+        (self.origins.len(), self.expansions.len())
+    }
+    /// Moves everything recorded since `mark` by `offset`, once the text it
+    /// was recorded against is placed at `offset` in its parent.
+    fn shift(&mut self, mark: (usize, usize), offset: usize) {
+        for (start, _) in &mut self.origins[mark.0..] {
+            *start += offset;
+        }
+        for expansion in &mut self.expansions[mark.1..] {
+            if let Some((start, end)) = &mut expansion.span {
+                *start += offset;
+                *end += offset;
+            }
+        }
+    }
+    fn forget_spans(&mut self, mark: (usize, usize)) {
+        for expansion in &mut self.expansions[mark.1..] {
+            expansion.span = None;
+        }
+    }
+}
+
+
+#[derive(Debug)]
+pub struct Preprocessor<'a> {
+    pub provider: &'a dyn FileProvider,
+    pub definitions: HashMap<String, Definition>,
+    /// Every file read so far, in the order they were included.
+    pub files: Vec<PathBuf>,
+    /// Directories searched, in order, when an include is not next to its includer.
+    pub include_paths: Vec<PathBuf>,
+    pub source_map: SourceMap,
+    /// Files being preprocessed, by canonical path and then as written.
+    stack: Vec<(PathBuf, PathBuf)>,
+    /// Canonical paths of files that used `/once` and are not included again.
+    once: HashSet<PathBuf>,
+    /// Open `/if` directives, where each of them is and whether it has reached its `/else`.
+    conditions: Vec<(Location, bool)>,
+} impl<'a> Preprocessor<'a> {
+    pub fn new(provider: &'a dyn FileProvider, definitions: HashMap<String, String>) -> Self {
+        let definitions = definitions
+            .into_iter()
+            .map(|(key, value)| (key, Definition { params: None, value, location: None }))
+            .collect();
+        Self {
+            provider,
+            definitions,
+            files: vec![],
+            include_paths: vec![],
+            source_map: SourceMap::default(),
+            stack: vec![],
+            once: HashSet::new(),
+            conditions: vec![],
+        }
+    }
+    pub fn preprocess<P: AsRef<Path> + AsRef<OsStr>>(&mut self, path: P) -> Result<String, Diagnostic>
+    {
+        let path = Path::new(&path);
+        self.files.push(path.to_path_buf());
+        if self.once.contains(&self.get_key(path)) {
+            return Ok(String::new());
+        }
+        let contents = self.provider.read(path).map_err(|_| format!("Could not read file '{}'", path.display()))?;
+        self.preprocess_source(&contents, path)
+    }
+    pub fn preprocess_source<P: AsRef<Path> + AsRef<OsStr>>(&mut self, contents: &str, path: P) -> Result<String, Diagnostic>
+    {
+        let path = Path::new(&path);
+        let key = self.get_key(path);
+        if let Some(index) = self.stack.iter().position(|(k, _)| *k == key) {
+            let mut chain: Vec<String> = self.stack[index..].iter().map(|(_, p)| p.display().to_string()).collect();
+            chain.push(path.display().to_string());
+            Err(format!("Include cycle: {}", chain.join(" -> ")))?;
+        }
+        self.stack.push((key, path.to_path_buf()));
+        let depth = self.conditions.len();
+        let line = Cell::new(1);
+        let chars = &mut contents.chars().inspect(|&c| if c == '\n' { line.set(line.get() + 1) }).peekable();
+        let result = self.preprocess_chars(chars, path, &line);
+        let result = result.map_err(|e| e.with_location(Location { path: path.to_path_buf(), line: line.get() }));
+        self.stack.pop();
+        if let (Ok(_), Some((location, _))) = (&result, self.conditions.get(depth)) {
+            Err(Diagnostic::from("Unclosed '/if'").with_location(location.clone()))?;
+        }
+        self.conditions.truncate(depth);
+        result
+    }
+    fn preprocess_chars<I>(&mut self, chars: &mut Peekable<I>, path: &Path, line: &Cell<usize>) -> Result<String, Diagnostic>
+    where I: Iterator<Item = char>
+    {
+        let mut result = String::new();
+        let mut is_line_start = true;
+        let mut origin_line = 0;
+        while let Some(c) = chars.next() {
+            let location = Location { path: path.to_path_buf(), line: line.get() };
+            if location.line != origin_line && c != '\n' {
+                origin_line = location.line;
+                self.source_map.origins.push((result.len(), location.clone()));
+            }
+            match c {
+                '/' => if let Some(c) = chars.next() {
+                    // Everything but block comments and directives runs to the end of its line,
+                    // so their errors point at the line they started on rather than the next one.
+                    let at_start = |e: Diagnostic| e.with_location(location.clone());
+                    is_line_start = match c {
+                        '/' => preprocess_comment(chars).map(|_| true)?,
+                        '*' => preprocess_multiline_comment(chars).map(|_| false)?,
+                        '=' => preprocess_set_definition(chars, &mut self.definitions, Some(location.clone()))
+                            .map_err(|e| at_start(e.into()))
+                            .map(|_| true)?,
+                        '#' => self.add_file(chars, path.parent().ok_or("Could not get parent dir")?, &mut result)
+                            .map_err(at_start)
+                            .map(|_| true)?,
+                        c if is_line_start && c.is_alphabetic() => self.preprocess_directive(c, chars, &mut result, &location)
+                            .map_err(at_start)?,
+                        c => {
+                            result.push('/');
+                            result.push(c);
+                            false
+                        },
+                    };
+                    // result.push('\n');
+                } else {
+                    result.push('/');
+                },
+                '?' => {
+                    let mark = self.source_map.mark();
+                    let value = self.expand(chars, &Scope::new(), 0, &location)?;
+                    self.source_map.shift(mark, result.len());
+                    result.push_str(&value);
+                    is_line_start = false;
+                },
+                _ => {
+                    result.push(c);
+                    is_line_start = c == '\n' || (is_line_start && c.is_whitespace());
+                },
+            }
+        }
+        Ok(result)
+    }
+    /// Handles a `/word` directive at the start of a line, returning whether
+    /// the directive consumed the rest of its line.
+    fn preprocess_directive<I>(&mut self, first: char, chars: &mut Peekable<I>, result: &mut String, location: &Location) -> Result<bool, Diagnostic>
+    where I: Iterator<Item = char>
+    {
+        let mut name = first.to_string();
+        while let Some(&c) = chars.peek() {
+            if c.is_alphanumeric() || c == '_' {
+                name.push(c);
+                chars.next();
             } else {
+                break;
+            }
+        }
+        match name.as_str() {
+            "once" => {
+                if let Some((key, _)) = self.stack.last() {
+                    self.once.insert(key.to_path_buf());
+                }
+                preprocess_comment(chars)?;
+                Ok(true)
+            },
+            "if" => {
+                let condition = read_line(chars);
+                self.conditions.push((location.clone(), false));
+                if !self.get_condition(&condition, location)? {
+                    self.skip_branch(chars, location)?;
+                }
+                Ok(true)
+            },
+            "else" => {
+                read_line(chars);
+                match self.conditions.last_mut() {
+                    Some((_, true)) => Err("Duplicate '/else'")?,
+                    Some((_, seen_else)) => *seen_else = true,
+                    None => Err("'/else' without an '/if'")?,
+                }
+                // The taken branch just ended, so everything up to '/endif' is skipped.
+                self.skip_branch(chars, location)?;
+                Ok(true)
+            },
+            "endif" => {
+                read_line(chars);
+                self.conditions.pop().ok_or("'/endif' without an '/if'")?;
+                Ok(true)
+            },
+            _ => {
                 result.push('/');
+                result.push_str(&name);
+                Ok(false)
             },
-            '?' => result.push_str(preprocess_get_definition(chars, definitions)?.as_str()),
-            _ => result.push(c),
         }
     }
-    Ok(result)
+    /// Evaluates an `/if` condition. `?NAME` is false when NAME is undefined,
+    /// empty, `0` or `false`, `!` negates and `==`/`!=` compare both sides.
+    fn get_condition(&mut self, condition: &str, location: &Location) -> Result<bool, Diagnostic> {
+        let condition = condition.trim();
+        if let Some(condition) = condition.strip_prefix('!') {
+            return Ok(!self.get_condition(condition, location)?);
+        }
+        for (operator, expected) in [("==", true), ("!=", false)] {
+            if let Some((left, right)) = condition.split_once(operator) {
+                let left = self.get_condition_value(left, location)?;
+                return Ok((left == self.get_condition_value(right, location)?) == expected);
+            }
+        }
+        let value = self.get_condition_value(condition, location)?;
+        Ok(!(value.is_empty() || value == "0" || value == "false"))
+    }
+    fn get_condition_value(&mut self, value: &str, location: &Location) -> Result<String, Diagnostic> {
+        let value = value.trim();
+        if let Some(name) = value.strip_prefix('?') {
+            if !self.definitions.contains_key(name) {
+                return Ok(String::new());
+            }
+            let mark = self.source_map.mark();
+            let value = self.expand(&mut name.chars().peekable(), &Scope::new(), 0, location)?;
+            self.source_map.forget_spans(mark);
+            return Ok(value.trim().to_string());
+        }
+        Ok(value.to_string())
+    }
+    /// Skips lines of a branch that is not taken, up to its '/else' or '/endif'.
+    /// `location` is the directive the branch starts after.
+    fn skip_branch<I>(&mut self, chars: &mut Peekable<I>, location: &Location) -> Result<(), Diagnostic>
+    where I: Iterator<Item = char>
+    {
+        let mut depth = 0;
+        let mut number = location.line;
+        while chars.peek().is_some() {
+            let line = read_line(chars);
+            number += 1;
+            let directive = line.trim_start().strip_prefix('/').unwrap_or("");
+            let name: String = directive.chars().take_while(|c| c.is_alphanumeric() || *c == '_').collect();
+            match name.as_str() {
+                "if" => depth += 1,
+                "endif" if depth == 0 => {
+                    self.conditions.pop();
+                    return Ok(());
+                },
+                "endif" => depth -= 1,
+                "else" if depth == 0 => {
+                    if let Some((_, seen_else)) = self.conditions.last_mut() {
+                        if *seen_else {
+                            let location = Location { path: location.path.to_path_buf(), line: number };
+                            Err(Diagnostic::from("Duplicate '/else'").with_location(location))?;
+                        }
+                        *seen_else = true;
+                    }
+                    return Ok(());
+                },
+                _ => (),
+            }
+        }
+        Ok(())
+    }
+    /// Expands the definition after a '?'. Arguments are expanded where they
+    /// are written and only the called definition sees them, so nested calls
+    /// never overwrite each other's arguments. Every expansion is recorded in
+    /// the source map with a span relative to the returned text.
+    pub fn expand<I>(&mut self, chars: &mut Peekable<I>, scope: &Scope, depth: usize, location: &Location) -> Result<String, Diagnostic>
+    where I: Iterator<Item = char>
+    {
+        let mut key = String::new();
+        while let Some(&c) = chars.peek() {
+            if c.is_alphanumeric() || c == '_' {
+                key.push(c);
+                chars.next();
+            } else {
+                break;
+            }
+        }
+        let is_call = chars.peek() == Some(&'(');
+        if let Some(value) = scope.get(&key) {
+            if is_call {
+                Err(format!("Parameter '?{key}' does not take arguments"))?;
+            }
+            return Ok(value.to_string());
+        }
+        let definition = self.definitions.get(&key).ok_or(format!("Unknown definition '?{key}'"))?.clone();
+        if depth >= MAX_EXPANSION_DEPTH {
+            Err(format!("Expanding '?{key}' recursed too deep"))?;
+        }
+        let index = self.source_map.expansions.len();
+        self.source_map.expansions.push(Expansion {
+            name: key.to_string(),
+            call: format!("?{key}"),
+            value: String::new(),
+            called_at: location.clone(),
+            defined_at: definition.location.clone(),
+            span: None,
+            depth,
+        });
+        let args = if is_call {
+            chars.next();
+            let written: Vec<String> = preprocess_args(chars)?.iter().map(|a| a.trim().to_string()).collect();
+            self.source_map.expansions[index].call = format!("?{key}({})", written.join("; "));
+            let mut args = vec![];
+            for arg in written {
+                let mark = self.source_map.mark();
+                args.push(self.expand_text(&arg, scope, depth + 1, location)?);
+                self.source_map.forget_spans(mark);
+            }
+            Some(args)
+        } else {
+            None
+        };
+        let scope = match (&definition.params, args) {
+            (Some(params), args) => {
+                let args = args.unwrap_or_default();
+                if params.len() != args.len() {
+                    Err(format!("Definition '?{key}' expects {} arguments but got {}", params.len(), args.len()))?;
+                }
+                params.iter().cloned().zip(args).collect()
+            },
+            (None, Some(args)) => args.into_iter().enumerate().map(|(i, a)| (i.to_string(), a)).collect(),
+            // Positional definitions called without arguments keep their caller's arguments.
+            (None, None) => scope.clone(),
+        };
+        let expansion = self.source_map.expansions[index].clone();
+        let body_location = definition.location.as_ref().unwrap_or(location);
+        let value = self.expand_text(&definition.value, &scope, depth + 1, body_location)
+            .map_err(|e| e.with_note(expansion.get_note()))?;
+        let expansion = &mut self.source_map.expansions[index];
+        expansion.value = value.to_string();
+        expansion.span = Some((0, value.len()));
+        Ok(value)
+    }
+    fn expand_text(&mut self, text: &str, scope: &Scope, depth: usize, location: &Location) -> Result<String, Diagnostic> {
+        let mut result = String::new();
+        let chars = &mut text.chars().peekable();
+        while let Some(c) = chars.next() {
+            if c == '?' {
+                let mark = self.source_map.mark();
+                let value = self.expand(chars, scope, depth, location)?;
+                self.source_map.shift(mark, result.len());
+                result.push_str(&value);
+            } else {
+                result.push(c);
+            }
+        }
+        Ok(result)
+    }
+    pub fn add_file<I, P: AsRef<Path> + AsRef<OsStr>>(&mut self, chars: &mut Peekable<I>, parent: P, result: &mut String) -> Result<(), Diagnostic>
+    where I: Iterator<Item = char>
+    {
+        let parent = Path::new(&parent);
+        let mut file_name = String::new();
+        for c in chars.by_ref() {
+            if c == '\n' {
+                break;
+            } else {
+                file_name.push(c);
+            }
+        }
+        let file_path = self.find_file(parent, file_name.trim())?;
+        let mark = self.source_map.mark();
+        let contents = self.preprocess(file_path)?;
+        self.source_map.shift(mark, result.len());
+        result.push_str(&contents);
+        Ok(())
+    }
+    /// Looks for `name` next to the including file and then in the include paths.
+    pub fn find_file(&self, parent: &Path, name: &str) -> Result<PathBuf, Diagnostic> {
+        let mut candidates = vec![parent.join(name)];
+        candidates.extend(self.include_paths.iter().map(|p| p.join(name)));
+        if let Some(file_path) = candidates.iter().find(|p| self.provider.exists(p)) {
+            return Ok(file_path.to_path_buf());
+        }
+        let mut message = format!("Could not find '{name}', looked in:");
+        for candidate in candidates {
+            message.push_str(&format!("\n    {}", candidate.display()));
+        }
+        Err(Diagnostic::from(message))
+    }
+    pub fn get_key(&self, path: &Path) -> PathBuf {
+        self.provider.canonicalize(path).unwrap_or_else(|_| normalize(path))
+    }
 }
 
 
-pub fn add_file<I, P: AsRef<Path> + AsRef<OsStr>>(chars: &mut Peekable<I>, parent: P, definitions: &mut HashMap<String, String>, result: &mut String) -> Result<(), &'static str>
+fn read_line<I>(chars: &mut Peekable<I>) -> String
 where I: Iterator<Item = char>
 {
-    let parent = Path::new(&parent);
-    let mut file_name = String::new();
-    while let Some(c) = chars.next() {
-        if c == '\n' {
-            break;
-        } else {
-            file_name.push(c);
-        }
-    }
-    let file_path = parent.join(file_name);
-    result.push_str(preprocess(file_path, definitions)?.as_str());
-    Ok(())
+    chars.by_ref().take_while(|&c| c != '\n').collect()
 }
 
 
 fn preprocess_comment<I>(chars: &mut Peekable<I>) -> Result<(), &'static str>
 where I: Iterator<Item = char>
 {
-    while let Some(c) = chars.next() {
+    for c in chars.by_ref() {
         if c == '\n' { break; }
     }
     Ok(())
@@ -82,10 +513,11 @@ where I: Iterator<Item = char>
 }
 
 
-pub fn preprocess_set_definition<I>(chars: &mut Peekable<I>, definitions: &mut HashMap<String, String>) -> Result<(), &'static str>
+pub fn preprocess_set_definition<I>(chars: &mut Peekable<I>, definitions: &mut HashMap<String, Definition>, location: Option<Location>) -> Result<(), &'static str>
 where I: Iterator<Item = char>
 {
     let mut key = String::new();
+    let mut params = None;
     if let Some(&c) = chars.peek() {
         if !(c.is_alphabetic() || c == '_' || c.is_whitespace()) {
             Err("Definition can only start with a letter or '_'")?;
@@ -94,6 +526,12 @@ where I: Iterator<Item = char>
     while let Some(c) = chars.next() {
         if c.is_whitespace() {
             break;
+        } else if c == '(' && !key.is_empty() {
+            params = Some(preprocess_params(chars)?);
+            if let Some(' ' | '\t') = chars.peek() {
+                chars.next();
+            }
+            break;
         } else if c.is_alphanumeric() || c == '_' {
             key.push(c);
         } else {
@@ -115,69 +553,220 @@ where I: Iterator<Item = char>
             value.push(c);
         }
     }
-    definitions.insert(key, value);
+    definitions.insert(key, Definition { params, value, location });
     Ok(())
 }
 
 
-pub fn preprocess_get_definition<I>(chars: &mut Peekable<I>, definitions: &mut HashMap<String, String>) -> Result<String, &'static str>
+fn preprocess_params<I>(chars: &mut Peekable<I>) -> Result<Vec<String>, &'static str>
 where I: Iterator<Item = char>
 {
-    let mut key = String::new();
-    while let Some(&c) = chars.peek() {
-        if c.is_alphanumeric() || c == '_' {
-            key.push(c);
-            chars.next();
-        } else {
-            break;
-        }
-    }
-    if let Some(&'(') = chars.peek() {
-        chars.next();
-        preprocess_set_args(chars, definitions)?;
-    }
-    if let Some(value) = definitions.get(&key) {
-        let mut result = String::new();
-        let temp = value.to_string();
-        let v_chars = &mut temp.chars().peekable();
-        while let Some(c) = v_chars.next() {
-            if c == '?' {
-                result.push_str(preprocess_get_definition(v_chars, definitions)?.as_str());
-            } else {
-                result.push(c);
+    let mut text = String::new();
+    for c in chars.by_ref() {
+        if c == ')' {
+            let mut params: Vec<String> = vec![];
+            for param in text.split([',', ';']).map(str::trim) {
+                if param.is_empty() && text.trim().is_empty() {
+                    break;
+                }
+                if !param.starts_with(|c: char| c.is_alphabetic() || c == '_') || !param.chars().all(|c| c.is_alphanumeric() || c == '_') {
+                    Err("Parameter names can only be alphanumeric or '_' and start with a letter or '_'")?;
+                }
+                if params.iter().any(|p| p == param) {
+                    Err("Duplicate parameter name")?;
+                }
+                params.push(param.to_string());
             }
+            return Ok(params);
         }
-        Ok(result.to_string())
-    } else {
-        Err("Unkown definition")
+        text.push(c);
     }
+    Err("Unclosed '(' in definition parameters")
 }
 
 
-pub fn preprocess_set_args<I>(chars: &mut Peekable<I>, definitions: &mut HashMap<String, String>) -> Result<(), &'static str>
+/// Reads the arguments of a definition call after its '(', split at the ';'s
+/// that are not nested in brackets.
+fn preprocess_args<I>(chars: &mut Peekable<I>) -> Result<Vec<String>, &'static str>
 where I: Iterator<Item = char>
 {
-    let mut index = 0;
+    let mut args = vec![];
     let mut value = String::new();
-    let mut paren_level = 1;
-    while let Some(c) = chars.next() {
-        if c == ';' {
-            definitions.insert(index.to_string(), value);
-            index += 1;
-            value = String::new();
-        } else if c == '(' {
-            paren_level += 1;
-        } else if c == ')' {
-            paren_level -= 1;
-            if paren_level == 0 {
-                definitions.insert(index.to_string(), value);
-                break;
-            }
-        } else {
-            value.push(c);
+    let mut brackets = vec![];
+    for c in chars.by_ref() {
+        match c {
+            ';' if brackets.is_empty() => args.push(take(&mut value)),
+            ')' if brackets.is_empty() => {
+                if !args.is_empty() || !value.trim().is_empty() {
+                    args.push(value);
+                }
+                return Ok(args);
+            },
+            '(' | '[' | '{' => {
+                brackets.push(c);
+                value.push(c);
+            },
+            ')' | ']' | '}' => {
+                let expected = match c {
+                    ')' => '(',
+                    ']' => '[',
+                    _ => '{',
+                };
+                if brackets.pop() != Some(expected) {
+                    Err("Mismatched brackets in definition arguments")?;
+                }
+                value.push(c);
+            },
+            c => value.push(c),
         }
     }
-    Ok(())
+    Err("Unclosed '(' in definition arguments")
 }
 
 
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::path::PathBuf;
+
+    use crate::preprocessor::files::MemoryFileProvider;
+    use crate::preprocessor::Preprocessor;
+
+    fn preprocess_files(files: &[(&str, &str)]) -> Result<String, String> {
+        preprocess_with_paths(files, &[])
+    }
+
+    fn preprocess_with_paths(files: &[(&str, &str)], include_paths: &[&str]) -> Result<String, String> {
+        let mut provider = MemoryFileProvider::new();
+        for (path, contents) in files {
+            provider.insert(path, contents);
+        }
+        let mut preprocessor = Preprocessor::new(&provider, HashMap::new());
+        preprocessor.include_paths = include_paths.iter().map(PathBuf::from).collect();
+        preprocessor.preprocess("main.dmn").map_err(|e| e.to_string())
+    }
+
+    #[test]
+    fn include_cycle_reports_chain() {
+        let error = preprocess_files(&[
+            ("main.dmn", "/#a.dmn\n"),
+            ("a.dmn", "/#b.dmn\n"),
+            ("b.dmn", "/#a.dmn\n"),
+        ]).unwrap_err();
+        assert_eq!(error, "b.dmn:1: Include cycle: a.dmn -> b.dmn -> a.dmn");
+    }
+
+    #[test]
+    fn self_include_is_a_cycle() {
+        let error = preprocess_files(&[("main.dmn", "/#main.dmn\n")]).unwrap_err();
+        assert_eq!(error, "main.dmn:1: Include cycle: main.dmn -> main.dmn");
+    }
+
+    #[test]
+    fn once_skips_repeated_includes() {
+        let output = preprocess_files(&[
+            ("main.dmn", "/#a.dmn\n/#a.dmn\n/#b.dmn\n"),
+            ("a.dmn", "/once\na\n"),
+            ("b.dmn", "/#a.dmn\nb\n"),
+        ]).unwrap();
+        assert_eq!(output, "a\nb\n");
+    }
+
+    #[test]
+    fn without_once_includes_repeat() {
+        let output = preprocess_files(&[("main.dmn", "/#a.dmn\n/#a.dmn\n"), ("a.dmn", "a\n")]).unwrap();
+        assert_eq!(output, "a\na\n");
+    }
+
+    #[test]
+    fn searches_next_to_file_then_include_paths_in_order() {
+        let paths = ["include", "desmon_path"];
+        let main = ("main.dmn", "/#lib.dmn\n");
+        let local = ("lib.dmn", "local");
+        let include = ("include/lib.dmn", "include");
+        let desmon_path = ("desmon_path/lib.dmn", "desmon_path");
+        assert_eq!(preprocess_with_paths(&[main, local, include, desmon_path], &paths).unwrap(), "local");
+        assert_eq!(preprocess_with_paths(&[main, include, desmon_path], &paths).unwrap(), "include");
+        assert_eq!(preprocess_with_paths(&[main, desmon_path], &paths).unwrap(), "desmon_path");
+    }
+
+    #[test]
+    fn missing_include_lists_every_candidate() {
+        let error = preprocess_with_paths(&[("main.dmn", "/#lib.dmn\n")], &["include", "desmon_path"]).unwrap_err();
+        assert_eq!(
+            error,
+            "main.dmn:1: Could not find 'lib.dmn', looked in:\n    lib.dmn\n    include/lib.dmn\n    desmon_path/lib.dmn",
+        );
+    }
+
+    #[test]
+    fn evaluates_conditions() {
+        let branch = |condition: &str| {
+            let source = format!("/=MODE teacher\n/=OFF 0\n/if {condition}\nyes\n/else\nno\n/endif\n");
+            preprocess_files(&[("main.dmn", &source)]).unwrap()
+        };
+        assert_eq!(branch("?MODE"), "yes\n");
+        assert_eq!(branch("?OFF"), "no\n");
+        assert_eq!(branch("?MISSING"), "no\n");
+        assert_eq!(branch("!?MISSING"), "yes\n");
+        assert_eq!(branch("!?MODE"), "no\n");
+        assert_eq!(branch("?MODE == teacher"), "yes\n");
+        assert_eq!(branch("?MODE == student"), "no\n");
+        assert_eq!(branch("?MODE != student"), "yes\n");
+        assert_eq!(branch("?MODE != teacher"), "no\n");
+    }
+
+    #[test]
+    fn nests_conditions() {
+        let source = "/=A 1\n/if ?A\na\n/if ?B\nb\n/else\nnot b\n/endif\n/else\n/if ?A\nskipped\n/endif\nnot a\n/endif\nend\n";
+        assert_eq!(preprocess_files(&[("main.dmn", source)]).unwrap(), "a\nnot b\nend\n");
+        let source = "/if ?B\n/if ?A\nskipped\n/else\nskipped\n/endif\n/else\nnot b\n/endif\n";
+        assert_eq!(preprocess_files(&[("main.dmn", source)]).unwrap(), "not b\n");
+    }
+
+    #[test]
+    fn rejects_unbalanced_conditions() {
+        assert_eq!(preprocess_files(&[("main.dmn", "/else\n")]).unwrap_err(), "main.dmn:1: '/else' without an '/if'");
+        assert_eq!(preprocess_files(&[("main.dmn", "/endif\n")]).unwrap_err(), "main.dmn:1: '/endif' without an '/if'");
+        assert_eq!(preprocess_files(&[("main.dmn", "/if 1\n/else\n/else\n/endif\n")]).unwrap_err(), "main.dmn:3: Duplicate '/else'");
+        assert_eq!(preprocess_files(&[("main.dmn", "/if 0\n/else\n/else\n/endif\n")]).unwrap_err(), "main.dmn:3: Duplicate '/else'");
+        assert_eq!(preprocess_files(&[("main.dmn", "/if 1\nx\n")]).unwrap_err(), "main.dmn:1: Unclosed '/if'");
+        assert_eq!(preprocess_files(&[("main.dmn", "a\n/if 0\n/if 1\n/endif\nx\n")]).unwrap_err(), "main.dmn:2: Unclosed '/if'");
+        assert_eq!(
+            preprocess_files(&[("main.dmn", "/#lib.dmn\n"), ("lib.dmn", "\n/if 1\n")]).unwrap_err(),
+            "lib.dmn:2: Unclosed '/if'",
+        );
+    }
+
+    #[test]
+    fn expands_named_parameters() {
+        let source = "/=add(a; b) (?a + ?b)\nx = ?add(1; ?add(2; 3));";
+        assert_eq!(preprocess_files(&[("main.dmn", source)]).unwrap(), "x = (1 + (2 + 3));");
+        let source = "/=twice(a) ?a ?a\nx = ?twice((1; 2));";
+        assert_eq!(preprocess_files(&[("main.dmn", source)]).unwrap(), "x = (1; 2) (1; 2);");
+        let source = "/=p ?0 - ?1\nx = ?p(1; 2);";
+        assert_eq!(preprocess_files(&[("main.dmn", source)]).unwrap(), "x = 1 - 2;");
+    }
+
+    #[test]
+    fn scopes_parameters_to_their_definition() {
+        let source = "/=f(a) ?a\n/=g(a) ?f(?a ?a)\nx = ?g(1);";
+        assert_eq!(preprocess_files(&[("main.dmn", source)]).unwrap(), "x = 1 1;");
+        let source = "/=f(a) ?a\nx = ?a;";
+        assert_eq!(preprocess_files(&[("main.dmn", source)]).unwrap_err(), "main.dmn:2: Unknown definition '?a'");
+        let source = "/=f(a) ?b\n/=g(b) ?f(1)\nx = ?g(2);";
+        assert_eq!(
+            preprocess_files(&[("main.dmn", source)]).unwrap_err(),
+            "main.dmn:3: Unknown definition '?b'\n    in expansion of ?f defined at main.dmn:1\n    in expansion of ?g defined at main.dmn:2",
+        );
+    }
+
+    #[test]
+    fn checks_arity() {
+        let add = "/=add(a; b) (?a + ?b)\n";
+        let error = preprocess_files(&[("main.dmn", &format!("{add}x = ?add(1);"))]).unwrap_err();
+        assert_eq!(error, "main.dmn:2: Definition '?add' expects 2 arguments but got 1");
+        let error = preprocess_files(&[("main.dmn", &format!("{add}x = ?add(1; 2; 3);"))]).unwrap_err();
+        assert_eq!(error, "main.dmn:2: Definition '?add' expects 2 arguments but got 3");
+    }
+}
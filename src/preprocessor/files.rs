@@ -0,0 +1,73 @@
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::fs::{canonicalize, read_to_string};
+use std::io;
+use std::path::{Component, Path, PathBuf};
+
+
+/// Where the preprocessor reads sources and includes from.
+pub trait FileProvider: Debug + Send + Sync {
+    fn read(&self, path: &Path) -> io::Result<String>;
+    fn exists(&self, path: &Path) -> bool {
+        self.read(path).is_ok()
+    }
+    /// A unique name for the file, used to notice the same file included twice.
+    fn canonicalize(&self, path: &Path) -> io::Result<PathBuf> {
+        Ok(normalize(path))
+    }
+}
+
+
+#[derive(Debug, Default)]
+pub struct DiskFileProvider;
+impl FileProvider for DiskFileProvider {
+    fn read(&self, path: &Path) -> io::Result<String> {
+        read_to_string(path)
+    }
+    fn exists(&self, path: &Path) -> bool {
+        path.is_file()
+    }
+    fn canonicalize(&self, path: &Path) -> io::Result<PathBuf> {
+        canonicalize(path)
+    }
+}
+
+
+/// Serves files from memory, for editors and tests. Paths are compared after
+/// removing `.` and resolving `..`, so `a/../b.dmn` finds `b.dmn`.
+#[derive(Debug, Default)]
+pub struct MemoryFileProvider {
+    files: HashMap<PathBuf, String>,
+} impl MemoryFileProvider {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn insert<P: AsRef<Path>>(&mut self, path: P, contents: &str) {
+        self.files.insert(normalize(path.as_ref()), contents.to_string());
+    }
+} impl FileProvider for MemoryFileProvider {
+    fn read(&self, path: &Path) -> io::Result<String> {
+        self.files
+            .get(&normalize(path))
+            .cloned()
+            .ok_or(io::Error::from(io::ErrorKind::NotFound))
+    }
+    fn exists(&self, path: &Path) -> bool {
+        self.files.contains_key(&normalize(path))
+    }
+}
+
+
+pub fn normalize(path: &Path) -> PathBuf {
+    let mut result = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => (),
+            Component::ParentDir => if !result.pop() {
+                result.push("..");
+            },
+            c => result.push(c),
+        }
+    }
+    result
+}
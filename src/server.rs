@@ -0,0 +1,127 @@
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::spawn;
+use std::time::Duration;
+
+
+/// Listens for reloads and applies the new state while keeping the current
+/// viewport. A changed page (template or options) falls back to a full reload.
+const LIVE_RELOAD_SCRIPT: &str = r"<script>
+    new EventSource('/events').addEventListener('reload', function (event) {
+        // Templates that do not keep the calculator in a global 'calculator'
+        // lose the viewport, but still get the new state.
+        if (event.data === 'page' || !window.calculator) {
+            location.reload();
+            return;
+        }
+        fetch('/state').then(function (response) { return response.json(); }).then(function (state) {
+            var current = calculator.getState();
+            state.graph.viewport = current.graph.viewport;
+            calculator.setState(state);
+        });
+    });
+</script>
+";
+
+
+#[derive(Default)]
+struct Page {
+    html: String,
+    shell: String,
+    state: String,
+    version: u64,
+    /// The version the page around the state last changed in.
+    page_version: u64,
+}
+
+
+#[derive(Default)]
+pub struct LiveServer {
+    page: Mutex<Page>,
+    changed: Condvar,
+} impl LiveServer {
+    pub fn update(&self, html: String, state: String) {
+        let mut page = self.page.lock().unwrap();
+        let html = Self::with_live_reload(html);
+        // Only the embedded state changed if the rest of the page is the same.
+        let shell = html.replacen(&state, "", 1);
+        page.version += 1;
+        if page.shell != shell {
+            page.page_version = page.version;
+        }
+        page.html = html;
+        page.shell = shell;
+        page.state = state;
+        self.changed.notify_all();
+    }
+    pub fn listen(self: Arc<Self>, address: &str) -> Result<(), &'static str> {
+        let listener = TcpListener::bind(address).map_err(|_| "Could not start the server")?;
+        spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let server = Arc::clone(&self);
+                spawn(move || server.respond(stream));
+            }
+        });
+        Ok(())
+    }
+    fn with_live_reload(html: String) -> String {
+        if let Some(index) = html.rfind("</body>") {
+            let mut result = html;
+            result.insert_str(index, LIVE_RELOAD_SCRIPT);
+            result
+        } else {
+            html + LIVE_RELOAD_SCRIPT
+        }
+    }
+    fn respond(&self, mut stream: TcpStream) {
+        let mut request = String::new();
+        let mut reader = BufReader::new(&stream);
+        if reader.read_line(&mut request).is_err() {
+            return;
+        }
+        let mut header = String::new();
+        while reader.read_line(&mut header).is_ok_and(|n| n > 2) {
+            header.clear();
+        }
+        let path = request.split_whitespace().nth(1).unwrap_or("/");
+        let (status, content_type, body) = match path {
+            "/" | "/index.html" => ("200 OK", "text/html", self.page.lock().unwrap().html.to_string()),
+            "/state" => ("200 OK", "application/json", self.page.lock().unwrap().state.to_string()),
+            "/events" => return self.stream_events(stream),
+            _ => ("404 Not Found", "text/plain", "Not found".to_string()),
+        };
+        let _ = write!(
+            stream,
+            "HTTP/1.1 {status}\r\nContent-Type: {content_type}; charset=utf-8\r\nContent-Length: {}\r\nCache-Control: no-store\r\nConnection: close\r\n\r\n{body}",
+            body.len(),
+        );
+    }
+    fn stream_events(&self, mut stream: TcpStream) {
+        let headers = "HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nCache-Control: no-store\r\nConnection: keep-alive\r\n\r\n";
+        if stream.write_all(headers.as_bytes()).is_err() {
+            return;
+        }
+        let mut version = self.page.lock().unwrap().version;
+        loop {
+            let page = self.page.lock().unwrap();
+            let (page, timeout) = self.changed
+                .wait_timeout_while(page, Duration::from_secs(15), |p| p.version == version)
+                .unwrap();
+            // The comment line doubles as a keep-alive that notices closed tabs.
+            let message = if timeout.timed_out() {
+                ": keep-alive\n\n".to_string()
+            } else {
+                // Several updates can land before a client wakes up, so it reloads
+                // the page if any of them changed it, not just the latest.
+                let data = if page.page_version > version { "page" } else { "state" };
+                version = page.version;
+                format!("event: reload\ndata: {data}\n\n")
+            };
+            drop(page);
+            if stream.write_all(message.as_bytes()).and_then(|_| stream.flush()).is_err() {
+                return;
+            }
+        }
+    }
+}